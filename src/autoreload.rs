@@ -0,0 +1,105 @@
+use crate::{config, logger, signal::Event};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::Duration
+};
+
+// delay after the last change to a configuration file before reloading
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+// watcher of the directories of the configuration files, with `auto-reload`
+pub struct ConfigWatcher {
+    watcher: RecommendedWatcher,
+    dirs: BTreeSet<PathBuf>
+}
+
+impl ConfigWatcher {
+    pub fn new(events_tx: Sender<Event>) -> Result<Self, notify::Error> {
+        let (tx, rx) = channel();
+        let watcher = notify::watcher(tx, DEBOUNCE)?;
+
+        thread::Builder::new()
+            .name("autoreload".to_owned())
+            .spawn(move || {
+                while let Ok(event) = rx.recv() {
+                    let paths = match event {
+                        DebouncedEvent::Create(path)
+                        | DebouncedEvent::Write(path)
+                        | DebouncedEvent::Chmod(path)
+                        | DebouncedEvent::Remove(path) => vec![path],
+                        DebouncedEvent::Rename(path_from, path_to) => vec![path_from, path_to],
+                        _ => continue
+                    };
+
+                    let config = config::OPTS.read().unwrap();
+
+                    if !paths.iter().any(|path| config.is_config_file(path)) {
+                        continue;
+                    }
+
+                    drop(config);
+
+                    // wait for the configuration files to settle
+                    while rx.recv_timeout(DEBOUNCE).is_ok() {}
+
+                    if events_tx.send(Event::ConfigChanged).is_err() {
+                        break;
+                    }
+                }
+            })
+            .map_err(notify::Error::Io)?;
+
+        let mut config_watcher = Self {
+            watcher,
+            dirs: BTreeSet::new()
+        };
+        config_watcher.update();
+
+        Ok(config_watcher)
+    }
+
+    // watch the directories of the current configuration files and `include`
+    // patterns, or none without `auto-reload`
+    pub fn update(&mut self) {
+        let dirs: BTreeSet<PathBuf> = {
+            let config = config::OPTS.read().unwrap();
+
+            if config.auto_reload {
+                config.config_dirs()
+            }
+            else {
+                BTreeSet::new()
+            }
+        };
+
+        for dir in self.dirs.difference(&dirs) {
+            let _ = self.watcher.unwatch(dir);
+        }
+
+        self.dirs.retain(|dir| dirs.contains(dir));
+
+        for dir in dirs {
+            if self.dirs.contains(&dir) {
+                continue;
+            }
+
+            match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    self.dirs.insert(dir);
+                }
+                Err(err) => {
+                    error!(
+                        logger::ROOT, "AUTORELOAD";
+                        "error" => true,
+                        "path" => dir.display(),
+                        "message" => err.to_string()
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,14 +1,53 @@
-use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg};
-use std::path::PathBuf;
+use crate::{control, logger};
+use clap::{
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, AppSettings, Arg,
+    ArgMatches, SubCommand
+};
+use slog::Level;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Duration
+};
 
 static DEFAULT_CONFIG_FILE: &str = "/etc/watchd/config.toml";
 
+#[derive(Clone)]
 pub struct Options {
     pub config_file: PathBuf,
     pub log_file: Option<PathBuf>,
+    pub log_format: Option<logger::Format>,
+    pub log_target: Option<logger::Target>,
+    pub log_level: Option<Level>,
+    pub history_file: Option<PathBuf>,
+    pub auto_reload: bool,
     pub init: bool,
     pub verbose: bool,
-    pub dry_run: bool
+    pub dry_run: bool,
+    pub subcommand: Option<Subcommand>
+}
+
+#[derive(Clone)]
+pub enum Subcommand {
+    History {
+        entry: Option<PathBuf>,
+        failed: bool,
+        since: Option<Duration>
+    },
+    ShowRun {
+        id: u64
+    },
+    Ctl {
+        socket: Option<PathBuf>,
+        json: bool,
+        request: control::Request
+    },
+    Tail {
+        socket: Option<PathBuf>,
+        json: bool,
+        entry: Option<PathBuf>
+    },
+    Check
 }
 
 impl Options {
@@ -30,6 +69,39 @@ impl Options {
                     .empty_values(false)
                     .value_name("FILE")
             )
+            .arg(
+                Arg::with_name("log-format")
+                    .long("log-format")
+                    .help("Specify log format")
+                    .possible_values(&["text", "json"])
+                    .value_name("FORMAT")
+            )
+            .arg(
+                Arg::with_name("log-target")
+                    .long("log-target")
+                    .help("Specify log target")
+                    .possible_values(&["stdout", "journald", "syslog"])
+                    .value_name("TARGET")
+            )
+            .arg(
+                Arg::with_name("log-level")
+                    .long("log-level")
+                    .help("Specify log level")
+                    .possible_values(&["error", "warning", "info", "debug", "trace"])
+                    .value_name("LEVEL")
+            )
+            .arg(
+                Arg::with_name("history-file")
+                    .long("history-file")
+                    .help("Specify run history file")
+                    .empty_values(false)
+                    .value_name("FILE")
+            )
+            .arg(
+                Arg::with_name("auto-reload")
+                    .long("auto-reload")
+                    .help("Reload the configuration when its file changes")
+            )
             .arg(
                 Arg::with_name("dry-run")
                     .short("d")
@@ -46,16 +118,200 @@ impl Options {
                 Arg::with_name("verbose")
                     .short("v")
                     .long("verbose")
-                    .help("Enable verbosity")
+                    .help("Enable verbosity (same as `--log-level debug`)")
+            )
+            .subcommand(
+                SubCommand::with_name("history")
+                    .about("List past command runs")
+                    .arg(
+                        Arg::with_name("entry")
+                            .long("entry")
+                            .help("Only list runs of the entry watching PATH")
+                            .empty_values(false)
+                            .value_name("PATH")
+                    )
+                    .arg(
+                        Arg::with_name("failed")
+                            .long("failed")
+                            .help("Only list failed runs")
+                    )
+                    .arg(
+                        Arg::with_name("since")
+                            .long("since")
+                            .help("Only list runs started in the last DURATION (e.g. 90s, 1h)")
+                            .empty_values(false)
+                            .value_name("DURATION")
+                            .validator(|value| {
+                                humantime::parse_duration(&value)
+                                    .map(|_| ())
+                                    .map_err(|err| err.to_string())
+                            })
+                    )
+            )
+            .subcommand(
+                SubCommand::with_name("show-run")
+                    .about("Show the details of a past command run")
+                    .arg(
+                        Arg::with_name("id")
+                            .help("Run identifier")
+                            .required(true)
+                            .value_name("ID")
+                            .validator(|value| {
+                                value
+                                    .parse::<u64>()
+                                    .map(|_| ())
+                                    .map_err(|err| err.to_string())
+                            })
+                    )
+            )
+            .subcommand(
+                SubCommand::with_name("check")
+                    .about("Validate the configuration file and report every problem")
+            )
+            .subcommand(
+                SubCommand::with_name("ctl")
+                    .about("Control the running daemon through its control socket")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .arg(
+                        Arg::with_name("socket")
+                            .long("socket")
+                            .help("Specify control socket (default: `control-socket`)")
+                            .empty_values(false)
+                            .value_name("FILE")
+                            .global(true)
+                    )
+                    .arg(
+                        Arg::with_name("json")
+                            .long("json")
+                            .help("Print the raw JSON response")
+                            .global(true)
+                    )
+                    .subcommand(
+                        SubCommand::with_name("status").about("Show the state of every entry")
+                    )
+                    .subcommand(
+                        SubCommand::with_name("trigger")
+                            .about("Run the commands of an entry immediately")
+                            .arg(self::entry_arg())
+                    )
+                    .subcommand(
+                        SubCommand::with_name("pause")
+                            .about("Ignore events for an entry")
+                            .arg(self::entry_arg())
+                    )
+                    .subcommand(
+                        SubCommand::with_name("resume")
+                            .about("Stop ignoring events for an entry")
+                            .arg(self::entry_arg())
+                    )
+                    .subcommand(SubCommand::with_name("reload").about("Reload the configuration"))
+            )
+            .subcommand(
+                SubCommand::with_name("tail")
+                    .about("Follow the live events of the running daemon")
+                    .arg(
+                        Arg::with_name("entry")
+                            .long("entry")
+                            .help("Only follow the events of the entry watching PATH")
+                            .empty_values(false)
+                            .value_name("PATH")
+                    )
+                    .arg(
+                        Arg::with_name("socket")
+                            .long("socket")
+                            .help("Specify control socket (default: `control-socket`)")
+                            .empty_values(false)
+                            .value_name("FILE")
+                    )
+                    .arg(
+                        Arg::with_name("json")
+                            .long("json")
+                            .help("Print the raw JSON events")
+                    )
             )
             .get_matches();
 
         Self {
             config_file: PathBuf::from(matches.value_of("config-file").unwrap()),
             log_file: matches.value_of("log-file").map(PathBuf::from),
+            log_format: matches
+                .value_of("log-format")
+                .and_then(logger::Format::from),
+            log_target: matches
+                .value_of("log-target")
+                .and_then(logger::Target::from),
+            log_level: matches.value_of("log-level").and_then(logger::level),
+            history_file: matches.value_of("history-file").map(PathBuf::from),
+            auto_reload: matches.is_present("auto-reload"),
             dry_run: matches.is_present("dry-run"),
             init: matches.is_present("init"),
-            verbose: matches.is_present("verbose")
+            verbose: matches.is_present("verbose"),
+            subcommand: match matches.subcommand() {
+                ("history", Some(matches)) => Some(Subcommand::History {
+                    entry: matches.value_of_os("entry").map(self::entry_path),
+                    failed: matches.is_present("failed"),
+                    since: matches
+                        .value_of("since")
+                        .map(|value| humantime::parse_duration(value).unwrap())
+                }),
+                ("show-run", Some(matches)) => Some(Subcommand::ShowRun {
+                    id: matches.value_of("id").unwrap().parse().unwrap()
+                }),
+                ("check", _) => Some(Subcommand::Check),
+                ("ctl", Some(matches)) => Some(Subcommand::Ctl {
+                    // global arguments may be given before or after the request
+                    socket: matches
+                        .value_of("socket")
+                        .or_else(|| self::ctl_request(matches).value_of("socket"))
+                        .map(PathBuf::from),
+                    json: matches.is_present("json")
+                        || self::ctl_request(matches).is_present("json"),
+                    request: match matches.subcommand() {
+                        ("trigger", Some(matches)) => control::Request::Trigger {
+                            entry: self::entry_value(matches)
+                        },
+                        ("pause", Some(matches)) => control::Request::Pause {
+                            entry: self::entry_value(matches)
+                        },
+                        ("resume", Some(matches)) => control::Request::Resume {
+                            entry: self::entry_value(matches)
+                        },
+                        ("reload", _) => control::Request::Reload,
+                        _ => control::Request::Status
+                    }
+                }),
+                ("tail", Some(matches)) => Some(Subcommand::Tail {
+                    socket: matches.value_of("socket").map(PathBuf::from),
+                    json: matches.is_present("json"),
+                    entry: matches.value_of_os("entry").map(self::entry_path)
+                }),
+                _ => None
+            }
         }
     }
 }
+
+fn entry_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("entry")
+        .help("Path of the entry")
+        .required(true)
+        .empty_values(false)
+        .value_name("ENTRY")
+}
+
+fn ctl_request<'a>(matches: &'a ArgMatches) -> &'a ArgMatches<'a> {
+    matches.subcommand().1.unwrap_or(matches)
+}
+
+fn entry_value(matches: &ArgMatches) -> PathBuf {
+    self::entry_path(matches.value_of_os("entry").unwrap())
+}
+
+// canonical path of an entry given on the command line, as in the
+// configuration, or `value` itself if it does not exist (e.g. an entry since
+// removed)
+fn entry_path(value: &OsStr) -> PathBuf {
+    Path::new(value)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(value))
+}
@@ -1,111 +1,623 @@
 use crate::{cli, logger};
-use regex::Regex;
-use std::{collections::HashMap, path::PathBuf, sync::RwLock};
+use regex::bytes::Regex;
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Deserializer
+};
+use slog::Level;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    net::SocketAddr,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Mutex, RwLock},
+    time::Duration
+};
+
+// number of runs retained in `output-dir` by default
+const DEFAULT_KEEP_RUNS: usize = 10;
+// number of rotated log files retained by default
+const DEFAULT_LOG_KEEP: usize = 5;
+// seconds between status file updates by default
+const DEFAULT_STATUS_INTERVAL: u64 = 60;
+// shortest interval between status file updates, which are not free
+const MIN_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+// keys of ConfigFromToml, including aliases
+const CONFIG_KEYS: &[&str] = &[
+    "log-file",
+    "log-rotate-size",
+    "log-rotate-interval",
+    "log-keep",
+    "log-format",
+    "log-target",
+    "syslog-facility",
+    "log-level",
+    "log-target-level",
+    "log-file-level",
+    "history-file",
+    "control-socket",
+    "metrics-address",
+    "status-file",
+    "status-interval",
+    "auto-reload",
+    "allow-unknown-keys",
+    "include",
+    "defaults",
+    "template",
+    "dry-run",
+    "init",
+    "verbose",
+    "entry"
+];
+// keys of FragmentFromToml
+const FRAGMENT_KEYS: &[&str] = &["entry"];
+// keys of EntryFromToml, including aliases
+const ENTRY_KEYS: &[&str] = &[
+    "path",
+    "extends",
+    "recursive",
+    "delay",
+    "excludes",
+    "exclude",
+    "commands",
+    "command",
+    "output-dir",
+    "keep-runs",
+    "log-level"
+];
+// keys of EntryFromToml in `[defaults]` and `[template.NAME]`, including
+// aliases
+const ENTRY_OPTION_KEYS: &[&str] = &[
+    "recursive",
+    "delay",
+    "excludes",
+    "exclude",
+    "commands",
+    "command",
+    "output-dir",
+    "keep-runs",
+    "log-level"
+];
 
 lazy_static! {
-    // configuration options
-    pub static ref OPTS: RwLock<Config> = RwLock::new(Config::from(cli::Options::load()));
+    // configuration options, loaded by `init`
+    pub static ref OPTS: RwLock<Config> = RwLock::new(
+        LOADED
+            .lock()
+            .unwrap()
+            .take()
+            .expect("Configuration used before being loaded")
+    );
+    // configuration loaded by `init`, moved to OPTS on first use
+    static ref LOADED: Mutex<Option<Config>> = Mutex::new(None);
 }
 
 pub enum Error {
-    Forbidden,
-    NotFound,
-    Invalid
+    Forbidden(String),
+    NotFound(PathBuf),
+    Invalid(String),
+    Unreadable(std::io::Error),
+    Problems(Vec<Problem>)
+}
+
+impl Error {
+    // whether the configuration could not be read for lack of permissions
+    pub fn permission_denied(&self) -> bool {
+        match self {
+            Error::Unreadable(err) => err.kind() == std::io::ErrorKind::PermissionDenied,
+            Error::Problems(problems) => problems
+                .iter()
+                .any(|problem| problem.error.permission_denied()),
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Forbidden(reason) => write!(f, "{}", reason),
+            Error::NotFound(path) => write!(f, "No such file or directory {}", path.display()),
+            Error::Invalid(reason) => write!(f, "{}", reason),
+            Error::Unreadable(err) => write!(f, "Could not open configuration file: {}", err),
+            // one problem per line
+            Error::Problems(problems) => write!(
+                f,
+                "{}",
+                problems
+                    .iter()
+                    .map(Problem::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )
+        }
+    }
+}
+
+// invalid option found in a configuration file, at `line` if it could be
+// located
+pub struct Problem {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub error: Error
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self::location(&self.file, self.line),
+            self.error
+        )
+    }
 }
 
 pub struct Config {
     pub log_file: Option<PathBuf>,
+    pub log_rotate_size: Option<u64>,
+    pub log_rotate_interval: Option<Duration>,
+    pub log_keep: usize,
+    pub log_format: logger::Format,
+    pub log_target: logger::Target,
+    pub syslog_facility: logger::Facility,
+    pub log_level: Level,
+    pub log_target_level: Option<Level>,
+    pub log_file_level: Option<Level>,
+    pub history_file: Option<PathBuf>,
+    pub control_socket: Option<PathBuf>,
+    pub metrics_address: Option<SocketAddr>,
+    pub status_file: Option<PathBuf>,
+    pub status_interval: Duration,
+    pub auto_reload: bool,
     pub dry_run: bool,
     pub init: bool,
-    pub verbose: bool,
     pub entries: HashMap<PathBuf, Entry>,
+    // absolute paths of the configuration file and included fragments
+    config_files: Vec<PathBuf>,
+    // absolute `include` patterns
+    includes: Vec<PathBuf>,
     options: cli::Options
 }
 
+// parse and validate the configuration, making it available as OPTS
+pub fn init(options: cli::Options) -> Result<(), Error> {
+    *LOADED.lock().unwrap() = Some(Config::load(options)?);
+
+    lazy_static::initialize(&OPTS);
+
+    Ok(())
+}
+
+// `history-file`, command line option first, read alone from the configuration
+// file: the history stays available while the rest of the configuration is
+// invalid (e.g. after a watched path is gone)
+pub fn history_file(options: &cli::Options) -> Result<Option<PathBuf>, Error> {
+    if let Some(history_file) = &options.history_file {
+        return Ok(Some(history_file.to_owned()));
+    }
+
+    let (source, query_toml) = self::query_toml(options)?;
+
+    Ok(query_toml
+        .history_file
+        .map(|history_file| source.resolve(&history_file)))
+}
+
+// `control-socket`, read alone from the configuration file like `history-file`:
+// `ctl` and `tail` reach a running daemon whatever the state of the file
+pub fn control_socket(options: &cli::Options) -> Result<Option<PathBuf>, Error> {
+    let (source, query_toml) = self::query_toml(options)?;
+
+    Ok(query_toml
+        .control_socket
+        .map(|control_socket| source.resolve(&control_socket)))
+}
+
+fn query_toml(options: &cli::Options) -> Result<(Source, QueryFromToml), Error> {
+    let source =
+        Source::read(&options.config_file).map_err(|problem| Error::Problems(vec![problem]))?;
+    let query_toml = source
+        .parse()
+        .map_err(|problem| Error::Problems(vec![problem]))?;
+
+    Ok((source, query_toml))
+}
+
 impl Config {
-    // convert ConfigFromToml to Config
-    pub fn from(options: cli::Options) -> Self {
-        // parse configuration file and command line options
-        let config_toml = ConfigFromToml::from(&options);
+    pub fn config_file(&self) -> &Path {
+        &self.options.config_file
+    }
 
-        // override file configuration with command line options
-        Self {
-            log_file: options.log_file.to_owned().or(config_toml.log_file),
-            dry_run: options.dry_run || config_toml.dry_run.unwrap_or_default(),
-            init: options.init || config_toml.init.unwrap_or_default(),
-            verbose: options.verbose || config_toml.verbose.unwrap_or_default(),
-            entries: config_toml
-                .entries
+    // whether `path` is, or would be, part of the configuration: the
+    // configuration file, an included fragment or a file matching an `include`
+    // pattern
+    pub fn is_config_file(&self, path: &Path) -> bool {
+        self.config_files
+            .iter()
+            .any(|config_file| config_file == path)
+            || self
+                .includes
                 .iter()
-                .map(|entry_toml| {
-                    // map EntryFromToml to (PathBuf, Entry)
-                    (
-                        // ensure `path` exists
-                        if entry_toml.path.exists() {
-                            entry_toml.path.to_owned()
-                        }
-                        else {
-                            panic!("No such file or directory {}", entry_toml.path.display());
-                        },
-                        Entry::from(entry_toml)
-                    )
-                })
-                .collect(),
-            options
+                .any(|include| self::matches(include, path))
+    }
+
+    // directories of the configuration file, included fragments and `include`
+    // patterns
+    pub fn config_dirs(&self) -> BTreeSet<PathBuf> {
+        self.config_files
+            .iter()
+            .chain(&self.includes)
+            .filter_map(|path| path.parent())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    // parse and validate the configuration again, leaving the current one
+    // untouched; options which require a restart shall not change
+    pub fn reload(&self) -> Result<Self, Error> {
+        let config = Self::load(self.options.clone())?;
+
+        if (
+            self.log_format,
+            self.log_target,
+            self.syslog_facility,
+            &self.control_socket,
+            self.metrics_address
+        ) != (
+            config.log_format,
+            config.log_target,
+            config.syslog_facility,
+            &config.control_socket,
+            config.metrics_address
+        ) {
+            return Err(Error::Forbidden(
+                "You shall not change `log-format`, `log-target`, `syslog-facility`, \
+                 `control-socket` or `metrics-address` without restarting"
+                    .to_owned()
+            ));
         }
+
+        Ok(config)
+    }
+
+    // parse and validate the configuration like `init`, also reporting commands
+    // which cannot be found
+    pub fn check(options: cli::Options) -> Result<Self, Vec<Problem>> {
+        Self::validate(options, true)
+    }
+
+    // parse and validate the configuration, failing with every problem found
+    fn load(options: cli::Options) -> Result<Self, Error> {
+        Self::validate(options, false).map_err(Error::Problems)
     }
 
-    pub fn reload(&mut self) -> Result<(), Error> {
+    // convert ConfigFromToml to Config, collecting the problems of every entry
+    fn validate(options: cli::Options, strict: bool) -> Result<Self, Vec<Problem>> {
         // parse configuration file and command line options
-        match ConfigFromToml::reload_from(&self.options) {
-            Ok(config_toml) => {
-                let log_file = self.options.log_file.to_owned().or(config_toml.log_file);
+        let source = Source::read(&options.config_file).map_err(|problem| vec![problem])?;
+        let allow_unknown_keys = source.allow_unknown_keys();
+        let mut problems = vec![];
+
+        // reject unknown keys (e.g. misspelled options), which may also explain a
+        // parse error (e.g. a misspelled required option)
+        if !allow_unknown_keys {
+            problems.extend(source.unknown_keys(CONFIG_KEYS));
+        }
+
+        let mut config_toml: ConfigFromToml = match source.parse() {
+            Ok(config_toml) => config_toml,
+            Err(problem) => {
+                problems.push(problem);
+
+                return Err(self::sorted(problems, &[source.file]));
+            }
+        };
+
+        // resolve relative paths against the directory of the configuration file
+        let resolve = |path: Option<PathBuf>| path.map(|path| source.resolve(&path));
+
+        config_toml.log_file = resolve(config_toml.log_file);
+        config_toml.history_file = resolve(config_toml.history_file);
+        config_toml.control_socket = resolve(config_toml.control_socket);
+        config_toml.status_file = resolve(config_toml.status_file);
+        config_toml.defaults = config_toml.defaults.resolve(&source);
+
+        for template in config_toml.templates.values_mut() {
+            *template = template.to_owned().resolve(&source);
+        }
+
+        // ensure `status_interval` is long enough not to rewrite the status file
+        // in a loop
+        if let Some(status_interval) = config_toml.status_interval {
+            if status_interval < MIN_STATUS_INTERVAL {
+                problems.push(source.problem(
+                    Section::Global,
+                    &["status-interval"],
+                    Error::Invalid(format!(
+                        "Status interval shall be at least {}: {}",
+                        humantime::format_duration(MIN_STATUS_INTERVAL),
+                        humantime::format_duration(status_interval)
+                    ))
+                ));
+            }
+        }
+
+        // check the options of `defaults` and of the templates once, rather than in
+        // every entry inheriting them
+        for (keys, reason) in config_toml.defaults.invalid() {
+            problems.push(source.problem(
+                Section::Table("defaults"),
+                keys,
+                Error::Invalid(format!("Defaults: {}", reason))
+            ));
+        }
+
+        for (name, template) in &config_toml.templates {
+            let section = format!("template.{}", name);
+
+            for (keys, reason) in template.invalid() {
+                problems.push(source.problem(
+                    Section::Table(&section),
+                    keys,
+                    Error::Invalid(format!("Template {}: {}", name, reason))
+                ));
+            }
+        }
+
+        // read and parse the fragments included by the configuration file, once
+        // each, in order
+        let mut files = vec![source.file.to_owned()];
+        let mut includes = vec![];
+        let mut fragments: Vec<(Source, FragmentFromToml)> = vec![];
+
+        for include in config_toml.includes.iter().flatten() {
+            let include = source.resolve(include);
+
+            let fragment_files = match self::glob(&include) {
+                Ok(fragment_files) => fragment_files,
+                Err(err) => {
+                    problems.push(source.problem(
+                        Section::Global,
+                        &["include"],
+                        Error::Invalid(format!(
+                            "Could not read included directory {}: {}",
+                            include.parent().unwrap_or(&include).display(),
+                            err
+                        ))
+                    ));
+                    continue;
+                }
+            };
 
-                if self.log_file != log_file {
-                    error!(
-                        logger::ROOT, "RELOAD";
-                        "reason" => "You shall not change `log_path` without restarting"
-                    );
+            includes.push(include);
 
-                    return Err(Error::Forbidden);
+            for fragment_file in fragment_files {
+                if files.contains(&fragment_file) {
+                    continue;
                 }
 
-                let mut entries: HashMap<PathBuf, Entry> =
-                    HashMap::with_capacity(config_toml.entries.len());
-
-                for entry_toml in config_toml.entries {
-                    // map EntryFromToml to (PathBuf, Entry)
-                    entries.insert(
-                        // ensure `path` exists
-                        if entry_toml.path.exists() {
-                            entry_toml.path.to_owned()
-                        }
-                        else {
-                            error!(
-                                logger::ROOT, "RELOAD";
-                                "reason" => "No such file or directory",
-                                "path" => entry_toml.path.display()
-                            );
-
-                            return Err(Error::NotFound);
-                        },
-                        match Entry::reload_from(&entry_toml) {
-                            Ok(entry) => entry,
-                            Err(err) => return Err(err)
-                        }
-                    );
+                files.push(fragment_file.to_owned());
+
+                let fragment = match Source::read(&fragment_file) {
+                    Ok(fragment) => fragment,
+                    Err(problem) => {
+                        problems.push(problem);
+                        continue;
+                    }
+                };
+
+                // fragments only hold entries
+                if !allow_unknown_keys {
+                    problems.extend(fragment.unknown_keys(FRAGMENT_KEYS));
+                }
+
+                match fragment.parse() {
+                    Ok(fragment_toml) => fragments.push((fragment, fragment_toml)),
+                    Err(problem) => problems.push(problem)
+                }
+            }
+        }
+
+        // entries of the configuration file and of the fragments, with their
+        // source and index in it
+        let entries_toml: Vec<(&Source, usize, &EntryFromToml)> = config_toml
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry_toml)| (&source, index, entry_toml))
+            .chain(fragments.iter().flat_map(|(fragment, fragment_toml)| {
+                fragment_toml
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, entry_toml)| (fragment, index, entry_toml))
+            }))
+            .collect();
+
+        let mut entries: HashMap<PathBuf, Entry> = HashMap::with_capacity(entries_toml.len());
+        // file and line of each entry `path`
+        let mut paths: HashMap<PathBuf, (&Path, Option<usize>)> =
+            HashMap::with_capacity(entries_toml.len());
+        // entry of each `output_dir`
+        let mut output_dirs: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+        for (entry_source, index, entry_toml) in entries_toml {
+            let problem = |keys, error| entry_source.problem(Section::Entry(index), keys, error);
+
+            // resolve relative paths against the directory of the file which defines
+            // the entry
+            let entry_toml = entry_toml.to_owned().resolve(entry_source);
+
+            // ensure `path` is set
+            let path = match &entry_toml.path {
+                Some(path) => path,
+                None => {
+                    problems.push(problem(
+                        &[],
+                        Error::Invalid("Entry shall have a path".to_owned())
+                    ));
+                    continue;
+                }
+            };
+
+            // ensure `path` exists, and refer to it canonically
+            let path = match path.canonicalize() {
+                Ok(path) => path,
+                Err(_) => {
+                    problems.push(problem(&["path"], Error::NotFound(path.to_owned())));
+                    path.to_owned()
+                }
+            };
+
+            // ensure `path` is watched once, across fragments
+            if let Some((file, line)) = paths.get(&path) {
+                problems.push(problem(
+                    &["path"],
+                    Error::Invalid(format!(
+                        "Entry {}: Duplicate entry, also defined at {}",
+                        path.display(),
+                        self::location(file, *line)
+                    ))
+                ));
+            }
+            else {
+                paths.insert(
+                    path.to_owned(),
+                    (
+                        &entry_source.file,
+                        entry_source.line(Section::Entry(index), &["path"])
+                    )
+                );
+            }
+
+            for (keys, reason) in entry_toml.invalid() {
+                problems.push(problem(
+                    keys,
+                    Error::Invalid(format!("Entry {}: {}", path.display(), reason))
+                ));
+            }
+
+            // options of the entry, falling back to those of its template, then to
+            // `defaults`
+            let mut entry_options = entry_toml.to_owned();
+
+            if let Some(name) = &entry_toml.extends {
+                match config_toml.templates.get(name) {
+                    Some(template) => entry_options = entry_options.or(template),
+                    None => {
+                        let names: Vec<&str> =
+                            config_toml.templates.keys().map(String::as_str).collect();
+
+                        problems.push(problem(
+                            &["extends"],
+                            Error::Invalid(format!(
+                                "Entry {}: Unknown template `{}`{}",
+                                path.display(),
+                                name,
+                                self::suggestion(name, &names)
+                            ))
+                        ));
+                    }
                 }
+            }
+
+            let entry_options = entry_options.or(&config_toml.defaults);
 
-                self.log_file = log_file;
-                self.dry_run = self.options.dry_run || config_toml.dry_run.unwrap_or_default();
-                self.init = self.options.init || config_toml.init.unwrap_or_default();
-                self.verbose = self.options.verbose || config_toml.verbose.unwrap_or_default();
-                self.entries = entries;
+            // ensure there is something to run
+            if entry_options.commands.is_none() {
+                problems.push(problem(
+                    &["commands", "command"],
+                    Error::Invalid(format!("Entry {}: No commands", path.display()))
+                ));
+            }
 
-                Ok(())
+            // ensure `output_dir` belongs to a single entry, as older runs are removed
+            // from it regardless of their entry
+            if let Some(output_dir) = &entry_options.output_dir {
+                match output_dirs.get(output_dir) {
+                    Some(other_path) => problems.push(problem(
+                        &["output-dir"],
+                        Error::Invalid(format!(
+                            "Entry {}: Output directory {} is also used by entry {}",
+                            path.display(),
+                            output_dir.display(),
+                            other_path.display()
+                        ))
+                    )),
+                    None => {
+                        output_dirs.insert(output_dir.to_owned(), path.to_owned());
+                    }
+                }
+            }
+
+            if strict {
+                // ensure the commands can be run by `sh`
+                for command in entry_options.commands.iter().flatten() {
+                    if let Some(program) = self::missing_program(command) {
+                        problems.push(problem(
+                            &["commands", "command"],
+                            Error::Invalid(format!(
+                                "Entry {}: Command not found: {}",
+                                path.display(),
+                                program
+                            ))
+                        ));
+                    }
+                }
             }
-            Err(_) => Err(Error::Invalid)
+
+            // map EntryFromToml to (PathBuf, Entry)
+            entries.insert(path, Entry::from(&entry_options));
+        }
+
+        if !problems.is_empty() {
+            return Err(self::sorted(problems, &files));
         }
+
+        let current_dir = std::env::current_dir().unwrap_or_default();
+
+        // override file configuration with command line options
+        Ok(Self {
+            log_file: options.log_file.to_owned().or(config_toml.log_file),
+            log_rotate_size: config_toml.log_rotate_size,
+            log_rotate_interval: config_toml.log_rotate_interval,
+            log_keep: config_toml.log_keep.unwrap_or(DEFAULT_LOG_KEEP),
+            log_format: options
+                .log_format
+                .or(config_toml.log_format)
+                .unwrap_or(logger::Format::Text),
+            log_target: options
+                .log_target
+                .or(config_toml.log_target)
+                .unwrap_or(logger::Target::Stdout),
+            syslog_facility: config_toml
+                .syslog_facility
+                .unwrap_or(logger::Facility::Daemon),
+            log_level: self::log_level(&options, config_toml.log_level, config_toml.verbose),
+            log_target_level: config_toml.log_target_level,
+            log_file_level: config_toml.log_file_level,
+            history_file: options.history_file.to_owned().or(config_toml.history_file),
+            control_socket: config_toml.control_socket,
+            metrics_address: config_toml.metrics_address,
+            status_file: config_toml.status_file,
+            status_interval: config_toml
+                .status_interval
+                .unwrap_or(Duration::from_secs(DEFAULT_STATUS_INTERVAL)),
+            auto_reload: options.auto_reload || config_toml.auto_reload.unwrap_or_default(),
+            dry_run: options.dry_run || config_toml.dry_run.unwrap_or_default(),
+            init: options.init || config_toml.init.unwrap_or_default(),
+            entries,
+            config_files: files.iter().map(|file| current_dir.join(file)).collect(),
+            includes: includes
+                .iter()
+                .map(|include| current_dir.join(include))
+                .collect(),
+            options
+        })
     }
 }
 
@@ -114,80 +626,626 @@ pub struct Entry {
     pub recursive: bool,
     pub delay: f64,
     pub excludes: Vec<Regex>,
-    pub commands: Vec<String>
+    pub commands: Vec<String>,
+    pub output_dir: Option<PathBuf>,
+    pub keep_runs: usize,
+    pub log_level: Option<Level>
+}
+
+// expressions are compared by their source
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.recursive == other.recursive
+            && self.delay == other.delay
+            && self
+                .excludes
+                .iter()
+                .map(Regex::as_str)
+                .eq(other.excludes.iter().map(Regex::as_str))
+            && self.commands == other.commands
+            && self.output_dir == other.output_dir
+            && self.keep_runs == other.keep_runs
+            && self.log_level == other.log_level
+    }
 }
 
 impl Entry {
-    // convert EntryFromToml to Entry
-    fn from(entry_toml: &EntryFromToml) -> Self {
+    // convert the effective EntryFromToml of an entry to Entry, once its options
+    // were checked by `EntryFromToml::invalid`
+    fn from(entry_options: &EntryFromToml) -> Self {
         Self {
-            recursive: entry_toml.recursive.unwrap_or_default(),
-            // ensure `delay` is not negative
-            delay: match entry_toml.delay {
-                Some(value) if value.is_sign_positive() => value,
-                Some(value) => panic!("Delay shall not be negative: {}", value),
-                None => f64::default()
-            },
-            excludes: entry_toml
+            recursive: entry_options.recursive.unwrap_or_default(),
+            delay: entry_options.delay.unwrap_or_default(),
+            excludes: entry_options
                 .excludes
-                .to_owned()
-                .unwrap_or_default()
                 .iter()
-                .map(|x| {
-                    // compile each exclude string
-                    Regex::new(x).unwrap_or_else(|_| panic!("Could not parse expression {:#?}", x))
-                })
+                .flatten()
+                .filter_map(|exclude| Regex::new(exclude).ok())
                 .collect(),
-            commands: entry_toml.commands.to_owned()
+            commands: entry_options.commands.to_owned().unwrap_or_default(),
+            output_dir: entry_options.output_dir.to_owned(),
+            keep_runs: entry_options.keep_runs.unwrap_or(DEFAULT_KEEP_RUNS),
+            log_level: entry_options.log_level
         }
     }
+}
 
-    fn reload_from(entry_toml: &EntryFromToml) -> Result<Self, Error> {
-        let mut excludes = vec![];
+// global log level: `log-level` or `verbose` (as `debug`), command line options
+// first
+fn log_level(options: &cli::Options, log_level: Option<Level>, verbose: Option<bool>) -> Level {
+    options
+        .log_level
+        .or_else(|| Some(Level::Debug).filter(|_| options.verbose))
+        .or(log_level)
+        .or_else(|| Some(Level::Debug).filter(|_| verbose.unwrap_or_default()))
+        .unwrap_or(Level::Info)
+}
 
-        for exclude in entry_toml.excludes.to_owned().unwrap_or_default() {
-            // compile each exclude string
-            match Regex::new(&exclude) {
-                Ok(expr) => excludes.push(expr),
-                Err(err) => {
-                    error!(
-                        logger::ROOT, "RELOAD";
-                        "reason" => "Could not parse expression",
-                        "message" => err.to_string()
-                    );
+// first word of `command`, if it is neither a builtin nor a program on `PATH`;
+// commands starting with shell syntax (e.g. assignments, subshells) are skipped
+fn missing_program(command: &str) -> Option<&str> {
+    let program = command.split_whitespace().next()?;
 
-                    return Err(Error::Invalid);
-                }
+    if program.contains(|c| "=$`'\"\\(){}<>;&|".contains(c)) {
+        return None;
+    }
+
+    // ask `sh`, which runs the commands, to resolve the program
+    let found = Command::new("sh")
+        .arg("-c")
+        .arg("command -v \"$1\"")
+        .arg("watchd")
+        .arg(program)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or_default();
+
+    Some(program).filter(|_| !found)
+}
+
+// `file:line`, or `file` alone
+fn location(file: &Path, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{}:{}", file.display(), line),
+        None => file.display().to_string()
+    }
+}
+
+// problems in the order of their files, then of their lines, unlocated ones
+// last
+fn sorted(mut problems: Vec<Problem>, files: &[PathBuf]) -> Vec<Problem> {
+    problems.sort_by_key(|problem| {
+        (
+            files.iter().position(|file| file == &problem.file),
+            problem.line.unwrap_or(usize::MAX)
+        )
+    });
+
+    problems
+}
+
+// files matching `pattern`, whose file name may contain `*` and `?` wildcards,
+// in lexical order; a pattern without wildcards matches its file, whether it
+// exists or not
+fn glob(pattern: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let name = pattern.file_name().unwrap_or_default().to_string_lossy();
+
+    if !name.contains(['*', '?']) {
+        return Ok(vec![pattern.to_owned()]);
+    }
+
+    let dir = pattern.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(if dir.as_os_str().is_empty() {
+        Path::new(".")
+    }
+    else {
+        dir
+    }) {
+        Ok(read_dir) => read_dir
+            .filter_map(Result::ok)
+            .map(|dir_entry| dir.join(dir_entry.file_name()))
+            .filter(|file| self::matches(pattern, file) && file.is_file())
+            .collect(),
+        // a missing drop-in directory has no fragments
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(err) => return Err(err)
+    };
+    files.sort();
+
+    Ok(files)
+}
+
+// whether `path` matches `pattern`, whose file name may contain `*` and `?`
+// wildcards; like shells, wildcards do not match a leading `.`
+fn matches(pattern: &Path, path: &Path) -> bool {
+    fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => {
+                wildcard(&pattern[1..], name) || (!name.is_empty() && wildcard(pattern, &name[1..]))
             }
+            (Some(b'?'), Some(_)) => wildcard(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p == n => wildcard(&pattern[1..], &name[1..]),
+            _ => false
+        }
+    }
+
+    let (pattern_name, name) = match (pattern.file_name(), path.file_name()) {
+        (Some(pattern_name), Some(name)) => (pattern_name.as_bytes(), name.as_bytes()),
+        _ => return false
+    };
+
+    if name.starts_with(b".") && !pattern_name.starts_with(b".") {
+        return false;
+    }
+
+    pattern.parent() == path.parent() && wildcard(pattern_name, name)
+}
+
+// ", did you mean `known_key`?" for the known key closest to `key`, if any is
+// close enough
+fn suggestion(key: &str, known_keys: &[&str]) -> String {
+    known_keys
+        .iter()
+        .map(|known_key| (strsim::jaro_winkler(key, known_key), known_key))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, known_key)| format!(", did you mean `{}`?", known_key))
+        .unwrap_or_default()
+}
+
+// keys of `table` (an entry, `defaults` or a template) which are not
+// `known_keys`, reported with `problem`
+fn unknown_table_keys<F: Fn(&str, Error) -> Problem>(
+    table: &toml::Value,
+    known_keys: &[&str],
+    name: &str,
+    problem: F
+) -> Vec<Problem> {
+    table
+        .as_table()
+        .into_iter()
+        .flat_map(|table| table.keys())
+        .filter(|key| !known_keys.contains(&key.as_str()))
+        .map(|key| {
+            problem(
+                key,
+                Error::Invalid(format!(
+                    "Unknown key `{}` in {}{}",
+                    key,
+                    name,
+                    self::suggestion(key, known_keys)
+                ))
+            )
+        })
+        .collect()
+}
+
+// section of a configuration file
+#[derive(Clone, Copy, PartialEq)]
+enum Section<'a> {
+    Global,
+    // `n`th `[[entry]]`
+    Entry(usize),
+    // `[name]`, e.g. `[defaults]`
+    Table(&'a str)
+}
+
+// contents of a configuration file, to parse it and locate its options
+struct Source {
+    file: PathBuf,
+    contents: String
+}
+
+impl Source {
+    fn read(file: &Path) -> Result<Self, Problem> {
+        match std::fs::read_to_string(file) {
+            Ok(contents) => Ok(Self {
+                file: file.to_owned(),
+                contents
+            }),
+            Err(err) => Err(Problem {
+                file: file.to_owned(),
+                line: None,
+                error: Error::Unreadable(err)
+            })
         }
+    }
 
-        Ok(Self {
-            recursive: entry_toml.recursive.unwrap_or_default(),
-            // ensure `delay` is not negative
-            delay: match entry_toml.delay {
-                Some(value) if value.is_sign_positive() => value,
-                Some(value) => {
-                    error!(
-                        logger::ROOT, "RELOAD";
-                        "reason" => "Delay shall not be negative",
-                        "value" => value
-                    );
-
-                    return Err(Error::Invalid);
-                }
-                None => f64::default()
-            },
-            excludes,
-            commands: entry_toml.commands.to_owned()
+    fn parse<T: DeserializeOwned>(&self) -> Result<T, Problem> {
+        toml::from_str(&self.contents).map_err(|err| Problem {
+            file: self.file.to_owned(),
+            line: err.line_col().map(|(line, _)| line + 1),
+            error: Error::Invalid(format!("Could not parse configuration file: {}", err))
         })
     }
+
+    // absolute `path`, relative to the directory of this file
+    fn resolve(&self, path: &Path) -> PathBuf {
+        std::env::current_dir()
+            .unwrap_or_default()
+            .join(self.file.parent().unwrap_or_else(|| Path::new("")))
+            .join(path)
+    }
+
+    fn allow_unknown_keys(&self) -> bool {
+        toml::from_str::<toml::Value>(&self.contents)
+            .ok()
+            .and_then(|value| value.get("allow-unknown-keys")?.as_bool())
+            .unwrap_or_default()
+    }
+
+    // keys which are not `global_keys` in the global section, or not options in
+    // entries
+    fn unknown_keys(&self, global_keys: &[&str]) -> Vec<Problem> {
+        let value: toml::Value = match toml::from_str(&self.contents) {
+            Ok(value) => value,
+            Err(_) => return vec![]
+        };
+
+        let mut problems = vec![];
+
+        let table = match value.as_table() {
+            Some(table) => table,
+            None => return problems
+        };
+
+        for key in table
+            .keys()
+            .filter(|key| !global_keys.contains(&key.as_str()))
+        {
+            problems.push(self.problem(
+                Section::Global,
+                &[key],
+                Error::Invalid(if CONFIG_KEYS.contains(&key.as_str()) {
+                    format!(
+                        "Option `{}` shall be set in the main configuration file",
+                        key
+                    )
+                }
+                else {
+                    format!(
+                        "Unknown key `{}`{}",
+                        key,
+                        self::suggestion(key, global_keys)
+                    )
+                })
+            ));
+        }
+
+        let entries = table
+            .get("entry")
+            .and_then(toml::Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        for (index, entry) in entries.iter().enumerate() {
+            problems.extend(self::unknown_table_keys(
+                entry,
+                ENTRY_KEYS,
+                "entry",
+                |key, error| self.problem(Section::Entry(index), &[key], error)
+            ));
+        }
+
+        if let Some(defaults) = table.get("defaults") {
+            problems.extend(self::unknown_table_keys(
+                defaults,
+                ENTRY_OPTION_KEYS,
+                "defaults",
+                |key, error| self.problem(Section::Table("defaults"), &[key], error)
+            ));
+        }
+
+        let templates = table
+            .get("template")
+            .and_then(toml::Value::as_table)
+            .into_iter()
+            .flatten();
+
+        for (name, template) in templates {
+            let section = format!("template.{}", name);
+
+            problems.extend(self::unknown_table_keys(
+                template,
+                ENTRY_OPTION_KEYS,
+                "template",
+                |key, error| self.problem(Section::Table(&section), &[key], error)
+            ));
+        }
+
+        problems
+    }
+
+    // `error`, located at the first of `keys` in `section`
+    fn problem(&self, section: Section, keys: &[&str], error: Error) -> Problem {
+        Problem {
+            file: self.file.to_owned(),
+            line: self.line(section, keys),
+            error
+        }
+    }
+
+    // line of the first of `keys` in `section`, falling back to its header
+    fn line(&self, section: Section, keys: &[&str]) -> Option<usize> {
+        // current section, `None` for those which are never looked for
+        let mut current = Some(Section::Global);
+        let mut entries = 0;
+        let mut header = None;
+        let mut line = None;
+
+        for (number, text) in self.contents.lines().enumerate() {
+            let text = text.trim();
+
+            if text.starts_with('[') {
+                let name = text.trim_matches(|c| c == '[' || c == ']').trim();
+
+                current = if text.starts_with("[[") {
+                    if name == "entry" {
+                        entries += 1;
+                        Some(Section::Entry(entries - 1))
+                    }
+                    else {
+                        None
+                    }
+                }
+                else {
+                    Some(Section::Table(name))
+                };
+
+                if current == Some(section) {
+                    header = Some(number + 1);
+                }
+
+                continue;
+            }
+
+            if current != Some(section) {
+                continue;
+            }
+
+            let key = match text.find('=') {
+                Some(position) => text[..position].trim().trim_matches('"'),
+                None => continue
+            };
+
+            if keys.contains(&key) {
+                line = Some(number + 1);
+                break;
+            }
+        }
+
+        line.or(header)
+    }
+}
+
+// how `interpolate` treats `${VAR}` when `VAR` is not in the environment
+#[derive(Clone, Copy, PartialEq)]
+enum Undefined {
+    // expand the default, or fail without one
+    Error,
+    // leave the expression to the shell running the command (e.g. a variable of
+    // a `for` loop)
+    Keep
+}
+
+// expand `${VAR}` and `${VAR:-default}` (when `VAR` is unset or empty) in
+// `value` with environment variables, leaving other shell expansions (e.g.
+// `${1}`, `${VAR%.*}`) alone; `$${` is a literal `${`
+fn interpolate(value: &str, undefined: Undefined) -> Result<String, String> {
+    self::interpolate_with(value, undefined, |name| std::env::var(name).ok())
+}
+
+// `interpolate`, looking variables up with `var`
+fn interpolate_with<F: Fn(&str) -> Option<String>>(
+    value: &str,
+    undefined: Undefined,
+    var: F
+) -> Result<String, String> {
+    let mut interpolated = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        interpolated.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            interpolated.push_str("${");
+            rest = after;
+            continue;
+        }
+
+        let end = match rest.find('}') {
+            Some(end) if rest.starts_with("${") => end,
+            _ => {
+                interpolated.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let expression = &rest[2..end];
+        let (name, default) = match expression.find(":-") {
+            Some(position) => (&expression[..position], Some(&expression[position + 2..])),
+            None => (expression, None)
+        };
+
+        let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !is_name {
+            interpolated.push_str(&rest[..=end]);
+        }
+        else {
+            match (var(name), default) {
+                (Some(value), None) => interpolated.push_str(&value),
+                (Some(value), Some(_)) if !value.is_empty() => interpolated.push_str(&value),
+                (None, _) if undefined == Undefined::Keep => interpolated.push_str(&rest[..=end]),
+                (_, Some(default)) => interpolated.push_str(default),
+                (None, None) => return Err(format!("undefined variable `{}`", name))
+            }
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    interpolated.push_str(rest);
+
+    Ok(interpolated)
+}
+
+// interpolate `value` as a path, expanding a leading `~` to `$HOME`
+fn interpolate_path(value: &str) -> Result<PathBuf, String> {
+    let value = self::interpolate(value, Undefined::Error)?;
+
+    match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = std::env::var_os("HOME")
+                .ok_or_else(|| format!("undefined variable `HOME` to expand {:?}", value))?;
+
+            Ok(PathBuf::from(format!("{}{}", home.to_string_lossy(), rest)))
+        }
+        _ => Ok(PathBuf::from(value))
+    }
+}
+
+// deserialize an optional path, interpolated
+fn deserialize_path<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<PathBuf>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+
+    self::interpolate_path(&value)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+// deserialize an optional list of paths, interpolated
+fn deserialize_paths<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<Vec<PathBuf>>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| self::interpolate_path(value))
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+// deserialize an optional list of strings, interpolated
+fn deserialize_strings<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<Vec<String>>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| self::interpolate(value, Undefined::Error))
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+// deserialize an optional list of commands, interpolated with the variables of
+// the environment only, the others being left to the shell
+fn deserialize_commands<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<Vec<String>>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| self::interpolate(value, Undefined::Keep))
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(D::Error::custom)
 }
 
 // configuration struct for TOML parsing (optional values and rename/alias)
 #[derive(Deserialize)]
 struct ConfigFromToml {
-    #[serde(rename = "log-file")]
+    #[serde(
+        rename = "log-file",
+        default,
+        deserialize_with = "self::deserialize_path"
+    )]
     log_file: Option<PathBuf>,
+    #[serde(
+        rename = "log-rotate-size",
+        default,
+        deserialize_with = "logger::deserialize_size"
+    )]
+    log_rotate_size: Option<u64>,
+    #[serde(
+        rename = "log-rotate-interval",
+        default,
+        deserialize_with = "logger::deserialize_duration"
+    )]
+    log_rotate_interval: Option<Duration>,
+    #[serde(rename = "log-keep")]
+    log_keep: Option<usize>,
+    #[serde(rename = "log-format")]
+    log_format: Option<logger::Format>,
+    #[serde(rename = "log-target")]
+    log_target: Option<logger::Target>,
+    #[serde(rename = "syslog-facility")]
+    syslog_facility: Option<logger::Facility>,
+    #[serde(
+        rename = "log-level",
+        default,
+        deserialize_with = "logger::deserialize_level"
+    )]
+    log_level: Option<Level>,
+    #[serde(
+        rename = "log-target-level",
+        default,
+        deserialize_with = "logger::deserialize_level"
+    )]
+    log_target_level: Option<Level>,
+    #[serde(
+        rename = "log-file-level",
+        default,
+        deserialize_with = "logger::deserialize_level"
+    )]
+    log_file_level: Option<Level>,
+    #[serde(
+        rename = "history-file",
+        default,
+        deserialize_with = "self::deserialize_path"
+    )]
+    history_file: Option<PathBuf>,
+    #[serde(
+        rename = "control-socket",
+        default,
+        deserialize_with = "self::deserialize_path"
+    )]
+    control_socket: Option<PathBuf>,
+    #[serde(rename = "metrics-address")]
+    metrics_address: Option<SocketAddr>,
+    #[serde(
+        rename = "status-file",
+        default,
+        deserialize_with = "self::deserialize_path"
+    )]
+    status_file: Option<PathBuf>,
+    #[serde(
+        rename = "status-interval",
+        default,
+        deserialize_with = "logger::deserialize_duration"
+    )]
+    status_interval: Option<Duration>,
+    #[serde(rename = "auto-reload")]
+    auto_reload: Option<bool>,
+    #[serde(
+        rename = "include",
+        default,
+        deserialize_with = "self::deserialize_paths"
+    )]
+    includes: Option<Vec<PathBuf>>,
+    #[serde(default)]
+    defaults: EntryFromToml,
+    #[serde(rename = "template", default)]
+    templates: HashMap<String, EntryFromToml>,
     #[serde(rename = "dry-run")]
     dry_run: Option<bool>,
     init: Option<bool>,
@@ -196,51 +1254,686 @@ struct ConfigFromToml {
     entries: Vec<EntryFromToml>
 }
 
-impl ConfigFromToml {
-    fn from(options: &cli::Options) -> Self {
-        // parse configuration from file
-        toml::from_str(
-            &std::fs::read_to_string(&options.config_file).unwrap_or_else(|err| {
-                panic!(
-                    "Could not open configuration file {:?}: {}",
-                    &options.config_file, err
-                )
-            })
-        )
-        .unwrap_or_else(|err| {
-            panic!(
-                "Could not parse configuration file {:#?}: {}",
-                &options.config_file, err
-            )
-        })
-    }
-
-    fn reload_from(options: &cli::Options) -> Result<Self, toml::de::Error> {
-        // parse configuration from file
-        toml::from_str(
-            &std::fs::read_to_string(&options.config_file).unwrap_or_else(|err| {
-                error!(
-                    logger::ROOT, "RELOAD";
-                    "reason" => "Could not open configuration file",
-                    "path" => options.config_file.display(),
-                    "message" => err.to_string()
-                );
+// included configuration fragment struct for TOML parsing
+#[derive(Deserialize)]
+struct FragmentFromToml {
+    #[serde(rename = "entry", default)]
+    entries: Vec<EntryFromToml>
+}
 
-                "".to_string()
-            })
-        )
-    }
+// `history-file` and `control-socket` of the configuration file, ignoring the
+// other keys
+#[derive(Deserialize)]
+struct QueryFromToml {
+    #[serde(
+        rename = "history-file",
+        default,
+        deserialize_with = "self::deserialize_path"
+    )]
+    history_file: Option<PathBuf>,
+    #[serde(
+        rename = "control-socket",
+        default,
+        deserialize_with = "self::deserialize_path"
+    )]
+    control_socket: Option<PathBuf>
 }
 
 // configuration entry struct for TOML parsing (optional values and
 // rename/alias)
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, Default)]
 struct EntryFromToml {
-    path: PathBuf,
+    // neither `path` nor `extends` apply to `[defaults]` and `[template.NAME]`
+    #[serde(default, deserialize_with = "self::deserialize_path")]
+    path: Option<PathBuf>,
+    extends: Option<String>,
     recursive: Option<bool>,
     delay: Option<f64>,
-    #[serde(alias = "exclude")]
+    #[serde(
+        alias = "exclude",
+        default,
+        deserialize_with = "self::deserialize_strings"
+    )]
     excludes: Option<Vec<String>>,
-    #[serde(alias = "command")]
-    commands: Vec<String>
+    #[serde(
+        alias = "command",
+        default,
+        deserialize_with = "self::deserialize_commands"
+    )]
+    commands: Option<Vec<String>>,
+    #[serde(
+        rename = "output-dir",
+        default,
+        deserialize_with = "self::deserialize_path"
+    )]
+    output_dir: Option<PathBuf>,
+    #[serde(rename = "keep-runs")]
+    keep_runs: Option<usize>,
+    #[serde(
+        rename = "log-level",
+        default,
+        deserialize_with = "logger::deserialize_level"
+    )]
+    log_level: Option<Level>
+}
+
+impl EntryFromToml {
+    // `self`, with relative paths resolved against the directory of `source`
+    fn resolve(self, source: &Source) -> Self {
+        Self {
+            path: self.path.map(|path| source.resolve(&path)),
+            output_dir: self
+                .output_dir
+                .map(|output_dir| source.resolve(&output_dir)),
+            ..self
+        }
+    }
+
+    // reasons why the options set in `self` are invalid, with their keys
+    fn invalid(&self) -> Vec<(&'static [&'static str], String)> {
+        let mut invalid = vec![];
+
+        // compile each exclude string
+        for exclude in self.excludes.iter().flatten() {
+            if let Err(err) = Regex::new(exclude) {
+                invalid.push((
+                    &["excludes", "exclude"][..],
+                    format!("Could not parse expression {:?}: {}", exclude, err)
+                ));
+            }
+        }
+
+        // ensure there is something to run
+        if let Some(commands) = &self.commands {
+            if commands.is_empty() {
+                invalid.push((&["commands", "command"][..], "No commands".to_owned()));
+            }
+
+            if commands.iter().any(|command| command.trim().is_empty()) {
+                invalid.push((
+                    &["commands", "command"],
+                    "Commands shall not be empty".to_owned()
+                ));
+            }
+        }
+
+        // ensure `delay` is a non-negative number of seconds
+        if let Some(delay) = self.delay {
+            if !delay.is_finite() || delay.is_sign_negative() {
+                invalid.push((
+                    &["delay"],
+                    format!("Delay shall be a non-negative number: {}", delay)
+                ));
+            }
+        }
+
+        // ensure `keep_runs` is positive
+        if self.keep_runs == Some(0) {
+            invalid.push((&["keep-runs"], "Keep runs shall be positive".to_owned()));
+        }
+
+        invalid
+    }
+
+    // options of `self`, falling back to those of `other`
+    fn or(self, other: &Self) -> Self {
+        Self {
+            path: self.path,
+            extends: self.extends,
+            recursive: self.recursive.or(other.recursive),
+            delay: self.delay.or(other.delay),
+            excludes: self.excludes.or_else(|| other.excludes.to_owned()),
+            commands: self.commands.or_else(|| other.commands.to_owned()),
+            output_dir: self.output_dir.or_else(|| other.output_dir.to_owned()),
+            keep_runs: self.keep_runs.or(other.keep_runs),
+            log_level: self.log_level.or(other.log_level)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // environment of the tests
+    fn var(name: &str) -> Option<String> {
+        match name {
+            "ROOT" => Some("/srv".to_owned()),
+            "EMPTY" => Some(String::new()),
+            _ => None
+        }
+    }
+
+    fn interpolate(value: &str, undefined: Undefined) -> Result<String, String> {
+        self::interpolate_with(value, undefined, self::var)
+    }
+
+    #[test]
+    fn interpolate_variables() {
+        assert_eq!(
+            interpolate("${ROOT}/data", Undefined::Error).unwrap(),
+            "/srv/data"
+        );
+        assert_eq!(
+            interpolate("${ROOT:-/tmp}", Undefined::Error).unwrap(),
+            "/srv"
+        );
+        assert_eq!(
+            interpolate("${MISSING:-/tmp}/a", Undefined::Error).unwrap(),
+            "/tmp/a"
+        );
+        assert_eq!(
+            interpolate("${ROOT}${ROOT}", Undefined::Error).unwrap(),
+            "/srv/srv"
+        );
+    }
+
+    #[test]
+    fn interpolate_empty() {
+        assert_eq!(interpolate("${EMPTY}/a", Undefined::Error).unwrap(), "/a");
+        assert_eq!(
+            interpolate("${EMPTY:-/tmp}", Undefined::Error).unwrap(),
+            "/tmp"
+        );
+        assert_eq!(interpolate("${EMPTY:-}", Undefined::Error).unwrap(), "");
+        assert_eq!(interpolate("${MISSING:-}", Undefined::Error).unwrap(), "");
+    }
+
+    #[test]
+    fn interpolate_undefined() {
+        assert_eq!(
+            interpolate("${MISSING}", Undefined::Error).unwrap_err(),
+            "undefined variable `MISSING`"
+        );
+
+        // left to the shell, even with a default
+        assert_eq!(
+            interpolate(r#"for f in "$@"; do echo "${f}"; done"#, Undefined::Keep).unwrap(),
+            r#"for f in "$@"; do echo "${f}"; done"#
+        );
+        assert_eq!(
+            interpolate("${MISSING:-a} ${ROOT} ${EMPTY:-b}", Undefined::Keep).unwrap(),
+            "${MISSING:-a} /srv b"
+        );
+    }
+
+    #[test]
+    fn interpolate_literal() {
+        assert_eq!(
+            interpolate("$${ROOT}", Undefined::Error).unwrap(),
+            "${ROOT}"
+        );
+        assert_eq!(
+            interpolate("$${MISSING}", Undefined::Error).unwrap(),
+            "${MISSING}"
+        );
+        assert_eq!(
+            interpolate("$$${ROOT}", Undefined::Keep).unwrap(),
+            "$${ROOT}"
+        );
+        assert_eq!(
+            interpolate("$ROOT $1 $$ $", Undefined::Error).unwrap(),
+            "$ROOT $1 $$ $"
+        );
+    }
+
+    #[test]
+    fn interpolate_unterminated() {
+        assert_eq!(interpolate("${ROOT", Undefined::Error).unwrap(), "${ROOT");
+        assert_eq!(
+            interpolate("a ${ROOT b", Undefined::Error).unwrap(),
+            "a ${ROOT b"
+        );
+        assert_eq!(
+            interpolate("${ROOT}/${", Undefined::Error).unwrap(),
+            "/srv/${"
+        );
+    }
+
+    #[test]
+    fn interpolate_expressions() {
+        for value in &[
+            "${1}",
+            "${#}",
+            "${@}",
+            "${ROOT%.*}",
+            "${ROOT#/}",
+            "${}",
+            "${1ROOT}"
+        ] {
+            assert_eq!(interpolate(value, Undefined::Error).unwrap(), *value);
+        }
+    }
+
+    #[test]
+    fn interpolate_home() {
+        let home = std::env::var("HOME").unwrap();
+
+        assert_eq!(interpolate_path("~").unwrap(), PathBuf::from(&home));
+        assert_eq!(
+            interpolate_path("~/a").unwrap(),
+            PathBuf::from(format!("{}/a", home))
+        );
+        assert_eq!(
+            interpolate_path("~user/a").unwrap(),
+            PathBuf::from("~user/a")
+        );
+        assert_eq!(interpolate_path("/a/~").unwrap(), PathBuf::from("/a/~"));
+    }
+
+    // empty directory of a test, removed beforehand
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("watchd-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    // entry of the test directory
+    const ENTRY: &str = "[[entry]]\npath = \".\"\ncommand = [\"true\"]\n";
+
+    // validate `contents` as `config.toml` of `dir`, returning the problems
+    fn problems(dir: &Path, contents: &str) -> Vec<String> {
+        let config_file = dir.join("config.toml");
+        fs::write(&config_file, contents).unwrap();
+
+        let options = cli::Options {
+            config_file,
+            log_file: None,
+            log_format: None,
+            log_target: None,
+            log_level: None,
+            history_file: None,
+            auto_reload: false,
+            init: false,
+            verbose: false,
+            dry_run: false,
+            subcommand: None
+        };
+
+        match Config::validate(options, false) {
+            Ok(_) => vec![],
+            Err(problems) => problems.iter().map(Problem::to_string).collect()
+        }
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let matches =
+            |pattern: &str, path: &str| self::matches(Path::new(pattern), Path::new(path));
+
+        assert!(matches("/d/*.toml", "/d/a.toml"));
+        assert!(matches("/d/*.toml", "/d/a.b.toml"));
+        assert!(!matches("/d/*.toml", "/d/a.toml.bak"));
+        assert!(matches("/d/?.toml", "/d/a.toml"));
+        assert!(!matches("/d/?.toml", "/d/ab.toml"));
+        assert!(!matches("/d/?.toml", "/d/.toml"));
+        assert!(matches("/d/a*b*c", "/d/abc"));
+        assert!(matches("/d/a*b*c", "/d/aXbYbc"));
+        assert!(!matches("/d/a*b*c", "/d/acb"));
+        assert!(matches("/d/*", "/d/a"));
+    }
+
+    #[test]
+    fn matches_directory() {
+        let matches =
+            |pattern: &str, path: &str| self::matches(Path::new(pattern), Path::new(path));
+
+        assert!(!matches("/d/*.toml", "/d/e/a.toml"));
+        assert!(!matches("/d/*.toml", "/a.toml"));
+        assert!(!matches("/d/*.toml", "/e/a.toml"));
+        assert!(!matches("/d/*", "/d"));
+    }
+
+    #[test]
+    fn matches_hidden() {
+        let matches =
+            |pattern: &str, path: &str| self::matches(Path::new(pattern), Path::new(path));
+
+        assert!(!matches("/d/*.toml", "/d/.a.toml"));
+        assert!(!matches("/d/*", "/d/.a"));
+        assert!(!matches("/d/?a", "/d/.a"));
+        assert!(matches("/d/.*.toml", "/d/.a.toml"));
+    }
+
+    #[test]
+    fn matches_literal() {
+        let matches =
+            |pattern: &str, path: &str| self::matches(Path::new(pattern), Path::new(path));
+
+        assert!(matches("/d/extra.toml", "/d/extra.toml"));
+        assert!(matches("/d/.extra.toml", "/d/.extra.toml"));
+        assert!(!matches("/d/extra.toml", "/d/extra.tom"));
+        assert!(!matches("/d/extra.toml", "/d/extra.toml.swp"));
+    }
+
+    #[test]
+    fn glob_directory() {
+        let dir = self::test_dir("glob");
+
+        for file in &["b.toml", "a.toml", ".c.toml", "d.txt", "e.toml~"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::create_dir(dir.join("f.toml")).unwrap();
+
+        assert_eq!(glob(&dir.join("*.toml")).unwrap(), vec![
+            dir.join("a.toml"),
+            dir.join("b.toml")
+        ]);
+        assert_eq!(glob(&dir.join("?.txt")).unwrap(), vec![dir.join("d.txt")]);
+        assert_eq!(glob(&dir.join("*.json")).unwrap(), Vec::<PathBuf>::new());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob_missing() {
+        let dir = self::test_dir("glob-missing").join("conf.d");
+
+        // a missing drop-in directory has no fragments
+        assert_eq!(glob(&dir.join("*.toml")).unwrap(), Vec::<PathBuf>::new());
+
+        // a file is included whether it exists or not
+        assert_eq!(glob(&dir.join("extra.toml")).unwrap(), vec![
+            dir.join("extra.toml")
+        ]);
+
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn line_multiline_array() {
+        let dir = self::test_dir("line-multiline-array");
+
+        // at the key opening the array, not at the offending element
+        assert_eq!(
+            problems(
+                &dir,
+                "[[entry]]\npath = \".\"\ncommand = [\n    \"true\",\n    \"\",\n]\n"
+            ),
+            vec![format!(
+                "{}:3: Entry {}: Commands shall not be empty",
+                dir.join("config.toml").display(),
+                dir.display()
+            )]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn line_quoted_key() {
+        let dir = self::test_dir("line-quoted-key");
+
+        assert_eq!(
+            problems(&dir, &format!("{}\"keep-runs\" = 0\n", ENTRY)),
+            vec![format!(
+                "{}:4: Entry {}: Keep runs shall be positive",
+                dir.join("config.toml").display(),
+                dir.display()
+            )]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn line_template() {
+        let dir = self::test_dir("line-template");
+
+        // in the template the problem belongs to, not the first one setting the key
+        assert_eq!(
+            problems(
+                &dir,
+                &format!(
+                    "[template.fast]\ndelay = 1.0\n[template.slow]\ndelay = -1.0\n{}",
+                    ENTRY
+                )
+            ),
+            vec![format!(
+                "{}:4: Template slow: Delay shall be a non-negative number: -1",
+                dir.join("config.toml").display()
+            )]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn line_fragment() {
+        let dir = self::test_dir("line-fragment");
+        let fragment = dir.join("conf.d").join("a.toml");
+        fs::create_dir_all(dir.join("conf.d").join("sub")).unwrap();
+        fs::write(
+            &fragment,
+            format!("\n{}{}keep-runs = 0\n", ENTRY.replace('.', "sub"), ENTRY)
+        )
+        .unwrap();
+
+        // in the fragment, counting its own entries
+        assert_eq!(
+            problems(
+                &dir,
+                &format!(
+                    "include = [\"conf.d/*.toml\"]\n{}",
+                    ENTRY.replace('.', "..")
+                )
+            ),
+            vec![format!(
+                "{}:8: Entry {}: Keep runs shall be positive",
+                fragment.display(),
+                dir.join("conf.d").display()
+            )]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn line_header_fallback() {
+        let dir = self::test_dir("line-header-fallback");
+
+        // at the header of the entry lacking the key
+        assert_eq!(
+            problems(&dir, &format!("{}[[entry]]\npath = \"..\"\n", ENTRY)),
+            vec![format!(
+                "{}:4: Entry {}: No commands",
+                dir.join("config.toml").display(),
+                dir.parent().unwrap().display()
+            )]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_unknown_keys() {
+        let dir = self::test_dir("unknown-keys");
+        let config_file = dir.join("config.toml");
+
+        assert_eq!(
+            problems(
+                &dir,
+                &format!(
+                    "[defaults]\nexlcude = []\n[template.slow]\nexlcude = []\n{}exlcude = []\n",
+                    ENTRY
+                )
+            ),
+            vec![
+                format!(
+                    "{}:2: Unknown key `exlcude` in defaults, did you mean `exclude`?",
+                    config_file.display()
+                ),
+                format!(
+                    "{}:4: Unknown key `exlcude` in template, did you mean `exclude`?",
+                    config_file.display()
+                ),
+                format!(
+                    "{}:8: Unknown key `exlcude` in entry, did you mean `exclude`?",
+                    config_file.display()
+                ),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_fragment_global_keys() {
+        let dir = self::test_dir("fragment-global-keys");
+        let fragment = dir.join("extra.toml");
+        fs::write(&fragment, "log-file = \"watchd.log\"\n").unwrap();
+
+        assert_eq!(
+            problems(&dir, &format!("include = [\"extra.toml\"]\n{}", ENTRY)),
+            vec![format!(
+                "{}:1: Option `log-file` shall be set in the main configuration file",
+                fragment.display()
+            )]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_allow_unknown_keys() {
+        let dir = self::test_dir("allow-unknown-keys");
+        fs::write(dir.join("extra.toml"), "log-file = \"watchd.log\"\n").unwrap();
+
+        assert!(problems(
+            &dir,
+            &format!(
+                "allow-unknown-keys = true\ninclude = [\"extra.toml\"]\ncomment = \
+                 \"\"\n[defaults]\nexlcude = []\n{}exlcude = []\n",
+                ENTRY
+            )
+        )
+        .is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_status_interval() {
+        let dir = self::test_dir("status-interval");
+        let config_file = dir.join("config.toml");
+
+        assert_eq!(
+            problems(&dir, &format!("status-interval = \"0s\"\n{}", ENTRY)),
+            vec![format!(
+                "{}:1: Status interval shall be at least 1s: 0s",
+                config_file.display()
+            )]
+        );
+        assert_eq!(
+            problems(&dir, &format!("status-interval = \"500ms\"\n{}", ENTRY)).len(),
+            1
+        );
+        assert!(problems(&dir, &format!("status-interval = \"1s\"\n{}", ENTRY)).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_output_dirs() {
+        let dir = self::test_dir("output-dirs");
+        let config_file = dir.join("config.toml");
+        fs::create_dir(dir.join("a")).unwrap();
+        fs::create_dir(dir.join("b")).unwrap();
+
+        // the same directory, however written
+        assert_eq!(
+            problems(
+                &dir,
+                "[[entry]]\npath = \"a\"\ncommand = [\"true\"]\noutput-dir = \
+                 \"out\"\n[[entry]]\npath = \"b\"\ncommand = [\"true\"]\noutput-dir = \"./out\"\n"
+            ),
+            vec![format!(
+                "{}:8: Entry {}: Output directory {} is also used by entry {}",
+                config_file.display(),
+                dir.join("b").display(),
+                dir.join("./out").display(),
+                dir.join("a").display()
+            )]
+        );
+
+        // shared through `defaults`
+        assert_eq!(
+            problems(
+                &dir,
+                "[defaults]\noutput-dir = \"out\"\n[[entry]]\npath = \"a\"\ncommand = \
+                 [\"true\"]\n[[entry]]\npath = \"b\"\ncommand = [\"true\"]\n"
+            )
+            .len(),
+            1
+        );
+
+        assert!(problems(
+            &dir,
+            "[[entry]]\npath = \"a\"\ncommand = [\"true\"]\noutput-dir = \
+             \"out/a\"\n[[entry]]\npath = \"b\"\ncommand = [\"true\"]\noutput-dir = \"out/b\"\n"
+        )
+        .is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entry_options_precedence() {
+        let entry: EntryFromToml = toml::from_str("delay = 1.0").unwrap();
+        let template: EntryFromToml = toml::from_str("delay = 2.0\nkeep-runs = 3").unwrap();
+        let defaults: EntryFromToml =
+            toml::from_str("delay = 3.0\nkeep-runs = 4\nrecursive = true").unwrap();
+
+        let entry_options = entry.or(&template).or(&defaults);
+
+        assert_eq!(entry_options.delay, Some(1.0));
+        assert_eq!(entry_options.keep_runs, Some(3));
+        assert_eq!(entry_options.recursive, Some(true));
+        assert!(entry_options.commands.is_none());
+    }
+
+    #[test]
+    fn validate_inherited_options() {
+        let dir = self::test_dir("inherited-options");
+        let config_file = dir.join("config.toml");
+
+        // reported once, at `defaults`, however many entries inherit it
+        assert_eq!(
+            problems(
+                &dir,
+                &format!(
+                    "[defaults]\nkeep-runs = 0\n{}{}",
+                    ENTRY,
+                    ENTRY.replace('.', "..")
+                )
+            ),
+            vec![format!(
+                "{}:2: Defaults: Keep runs shall be positive",
+                config_file.display()
+            )]
+        );
+
+        // reported once, at the template, even where an entry overrides it
+        assert_eq!(
+            problems(
+                &dir,
+                &format!(
+                    "[template.slow]\ndelay = -1.0\n{}extends = \"slow\"\n{}extends = \
+                     \"slow\"\ndelay = 1.0\n",
+                    ENTRY,
+                    ENTRY.replace('.', "..")
+                )
+            ),
+            vec![format!(
+                "{}:2: Template slow: Delay shall be a non-negative number: -1",
+                config_file.display()
+            )]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,302 @@
+use crate::{config, history, logger, signal::Event, state, stream};
+use std::{
+    fs::{self, Permissions},
+    io::{BufRead, BufReader, Error, ErrorKind, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream}
+    },
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Sender},
+    thread
+};
+
+// control request, one JSON object per line, e.g. `{"command": "trigger",
+// "entry": "/etc"}`
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    Trigger {
+        entry: PathBuf
+    },
+    Pause {
+        entry: PathBuf
+    },
+    Resume {
+        entry: PathBuf
+    },
+    Reload,
+    LastRun {
+        entry: PathBuf
+    },
+    // handled by the control server itself, streaming live events
+    Subscribe {
+        #[serde(default)]
+        entry: Option<PathBuf>
+    }
+}
+
+impl Request {
+    fn name(&self) -> &'static str {
+        match self {
+            Request::Status => "status",
+            Request::Trigger { .. } => "trigger",
+            Request::Pause { .. } => "pause",
+            Request::Resume { .. } => "resume",
+            Request::Reload => "reload",
+            Request::LastRun { .. } => "last-run",
+            Request::Subscribe { .. } => "subscribe"
+        }
+    }
+}
+
+// control response, one JSON object per line
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Response {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<Vec<EntryStatus>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run: Option<history::Run>
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn error<S: ToString>(error: S) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EntryStatus {
+    pub path: String,
+    #[serde(flatten)]
+    pub state: state::Entry
+}
+
+// serve the control socket at `socket_path`, forwarding requests to the main
+// loop through `events_tx`
+pub fn spawn(socket_path: &Path, events_tx: Sender<Event>) -> Result<(), Error> {
+    // remove a stale socket left by a previous instance, but neither the socket
+    // of a running instance nor anything else
+    match fs::symlink_metadata(socket_path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "Path already exists and is not a socket"
+            ))
+        }
+        Ok(_) if UnixStream::connect(socket_path).is_ok() => {
+            return Err(Error::new(
+                ErrorKind::AddrInUse,
+                "Socket already in use by another instance"
+            ))
+        }
+        Ok(_) => fs::remove_file(socket_path)?,
+        Err(_) => {}
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+
+    // only the owner may control the daemon
+    fs::set_permissions(socket_path, Permissions::from_mode(0o600))?;
+
+    thread::Builder::new()
+        .name("control".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let events_tx = events_tx.clone();
+
+                        let _ = thread::Builder::new()
+                            .name("control-client".to_owned())
+                            .spawn(move || self::serve(stream, events_tx));
+                    }
+                    Err(err) => {
+                        error!(
+                            logger::ROOT, "CONTROL";
+                            "error" => true,
+                            "message" => err.to_string()
+                        );
+                    }
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn serve(stream: UnixStream, events_tx: Sender<Event>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe { entry })
+                if entry.as_ref().is_none_or(|entry| {
+                    config::OPTS.read().unwrap().entries.contains_key(entry)
+                }) =>
+            {
+                info!(
+                    logger::ROOT, "CONTROL";
+                    "command" => "subscribe"
+                );
+
+                // the connection is dedicated to the stream from now on
+                return stream::subscribe(writer, entry);
+            }
+            Ok(Request::Subscribe { entry: Some(entry) }) => {
+                Response::error(format!("No such entry {}", entry.display()))
+            }
+            Ok(request) => {
+                info!(
+                    logger::ROOT, "CONTROL";
+                    "command" => request.name()
+                );
+
+                // wait for the main loop to handle the request
+                let (response_tx, response_rx) = channel();
+
+                if events_tx
+                    .send(Event::Control(request, response_tx))
+                    .is_err()
+                {
+                    break;
+                }
+
+                match response_rx.recv() {
+                    Ok(response) => response,
+                    Err(_) => break
+                }
+            }
+            Err(err) => Response::error(format!("Invalid request: {}", err))
+        };
+
+        let mut line = match serde_json::to_string(&response) {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        line.push('\n');
+
+        if writer.write_all(line.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+// send `request` to the running daemon and print its response, as a table or
+// as raw JSON
+pub fn request(socket_path: Option<PathBuf>, json: bool, request: Request) -> Result<(), Error> {
+    let mut stream = self::connect(socket_path)?;
+
+    let mut line = serde_json::to_string(&request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    if json {
+        print!("{}", line);
+    }
+
+    let response: Response = serde_json::from_str(&line)?;
+
+    if !response.ok {
+        return Err(Error::other(response.error.unwrap_or_default()));
+    }
+
+    if json {
+        return Ok(());
+    }
+
+    match response.entries {
+        Some(entries) => self::print_entries(&entries),
+        None => println!("OK")
+    }
+
+    Ok(())
+}
+
+fn print_entries(entries: &[EntryStatus]) {
+    println!(
+        "{:<8} {:<20} {:<8} {:<20} {:<7}  ENTRY",
+        "STATE", "LAST EVENT", "LAST RUN", "LAST RUN START", "STATUS"
+    );
+
+    for entry in entries {
+        let (id, start, status) = match &entry.state.last_run {
+            Some(run) => (
+                run.id.to_string(),
+                history::format_timestamp(run.start),
+                if run.success { "success" } else { "failure" }
+            ),
+            None => ("-".to_owned(), "-".to_owned(), "-")
+        };
+
+        println!(
+            "{:<8} {:<20} {:<8} {:<20} {:<7}  {}",
+            if entry.state.error.is_some() {
+                "error"
+            }
+            else if entry.state.running {
+                "running"
+            }
+            else if entry.state.paused {
+                "paused"
+            }
+            else {
+                "watching"
+            },
+            entry
+                .state
+                .last_event
+                .map(history::format_timestamp)
+                .unwrap_or_else(|| "-".to_owned()),
+            id,
+            start,
+            status,
+            entry.path
+        );
+    }
+}
+
+// connect to the control socket at `socket_path`, `--socket` or
+// `control-socket`
+pub fn connect(socket_path: Option<PathBuf>) -> Result<UnixStream, Error> {
+    let socket_path = socket_path
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "No `control-socket` configured"))?;
+
+    UnixStream::connect(&socket_path).map_err(|err| {
+        Error::new(
+            err.kind(),
+            format!("Could not connect to {}: {}", socket_path.display(), err)
+        )
+    })
+}
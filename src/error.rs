@@ -0,0 +1,53 @@
+use crate::config;
+use std::{fmt, io};
+
+// exit statuses, from sysexits.h
+const EX_NOPERM: i32 = 77;
+const EX_CONFIG: i32 = 78;
+
+// failure which stops the program
+pub enum Error {
+    // invalid or unreadable configuration
+    Config(config::Error),
+    // failure to set up or run the daemon or a subcommand
+    Io(io::Error)
+}
+
+impl Error {
+    // exit status of the program: 77 when lacking permissions, 78 on invalid
+    // configurations, 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(err) if err.permission_denied() => EX_NOPERM,
+            Error::Config(_) => EX_CONFIG,
+            Error::Io(err) if err.kind() == io::ErrorKind::PermissionDenied => EX_NOPERM,
+            Error::Io(_) => 1
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+impl From<config::Error> for Error {
+    fn from(err: config::Error) -> Self {
+        Error::Config(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+// add `context` to the message of `err`, keeping its kind
+pub fn context(err: io::Error, context: &str) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", context, err))
+}
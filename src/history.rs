@@ -0,0 +1,292 @@
+use crate::config;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Error, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+// maximum amount of bytes of stdout/stderr stored per command
+const OUTPUT_LIMIT: usize = 4096;
+
+lazy_static! {
+    // history file and identifier of the last allocated run; access to the history file is
+    // serialized through this lock
+    static ref LAST_ID: Mutex<Option<(Option<PathBuf>, u64)>> = Mutex::new(None);
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Run {
+    pub id: u64,
+    pub entry: String,
+    pub paths: Vec<String>,
+    // seconds since the UNIX epoch
+    pub start: f64,
+    pub end: f64,
+    pub success: bool,
+    pub commands: Vec<CommandRun>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CommandRun {
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub wall_time: f64,
+    pub stdout: String,
+    pub stderr: String,
+    pub error: Option<String>
+}
+
+impl Run {
+    pub fn new(entry: &Path, paths: &[PathBuf], start: SystemTime) -> Self {
+        Self {
+            id: self::next_id(),
+            entry: entry.display().to_string(),
+            paths: paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            start: self::timestamp(start),
+            end: self::timestamp(start),
+            success: true,
+            commands: vec![]
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+impl CommandRun {
+    // keep at most `OUTPUT_LIMIT` bytes of `output`, followed by `[...]` when cut
+    pub fn truncate(output: &[u8]) -> String {
+        let mut end = output.len().min(self::OUTPUT_LIMIT);
+
+        // don't cut a multi-byte character in half
+        while end < output.len() && end + 3 > self::OUTPUT_LIMIT && output[end] & 0xc0 == 0x80 {
+            end -= 1;
+        }
+
+        let mut text = String::from_utf8_lossy(&output[..end]).into_owned();
+        let truncated = end < output.len() || text.len() > self::OUTPUT_LIMIT;
+
+        // invalid bytes grow into 3-byte replacement characters
+        if text.len() > self::OUTPUT_LIMIT {
+            let mut end = self::OUTPUT_LIMIT;
+
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            text.truncate(end);
+        }
+
+        if truncated {
+            text.push_str("[...]");
+        }
+
+        text
+    }
+}
+
+pub fn timestamp(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+pub fn format_timestamp(timestamp: f64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs_f64(timestamp)).to_string()
+}
+
+// allocate the identifier of a new run, resuming the numbering from the history
+// file (if any)
+pub fn next_id() -> u64 {
+    let history_file = config::OPTS.read().unwrap().history_file.to_owned();
+    let mut last_id = LAST_ID.lock().unwrap();
+
+    let id = match &*last_id {
+        Some((path, id)) if *path == history_file => *id,
+        _ => history_file
+            .as_ref()
+            .and_then(|history_file| self::load(history_file).ok())
+            .and_then(|runs| runs.iter().map(|run| run.id).max())
+            .unwrap_or_default()
+    } + 1;
+
+    *last_id = Some((history_file, id));
+
+    id
+}
+
+// append `run` to the history file
+pub fn record(run: &Run) -> Result<(), Error> {
+    let history_file = match config::OPTS.read().unwrap().history_file.to_owned() {
+        Some(history_file) => history_file,
+        None => return Ok(())
+    };
+
+    let mut line = serde_json::to_string(run)?;
+    line.push('\n');
+
+    // serialize appends
+    let _lock = LAST_ID.lock().unwrap();
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history_file)?
+        .write_all(line.as_bytes())
+}
+
+// read every run from `history_file`, skipping malformed lines
+pub fn load(history_file: &Path) -> Result<Vec<Run>, Error> {
+    let file = match File::open(history_file) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err)
+    };
+
+    Ok(BufReader::new(file)
+        .lines()
+        .filter_map(|line| serde_json::from_str(&line.ok()?).ok())
+        .collect())
+}
+
+// print the runs matching the given filters, oldest first
+pub fn list(
+    history_file: Option<PathBuf>,
+    entry: Option<&Path>,
+    failed: bool,
+    since: Option<Duration>
+) -> Result<(), Error> {
+    let history_file = self::history_file(history_file)?;
+    let since = since.map(|since| self::timestamp(SystemTime::now() - since));
+
+    println!(
+        "{:<8} {:<20} {:>10} {:<7} {:>5}  ENTRY",
+        "ID", "START", "DURATION", "STATUS", "PATHS"
+    );
+
+    for run in self::load(&history_file)? {
+        if entry.is_some_and(|entry| Path::new(&run.entry) != entry)
+            || (failed && run.success)
+            || since.is_some_and(|since| run.start < since)
+        {
+            continue;
+        }
+
+        println!(
+            "{:<8} {:<20} {:>9.3}s {:<7} {:>5}  {}",
+            run.id,
+            self::format_timestamp(run.start),
+            run.duration(),
+            if run.success { "success" } else { "failure" },
+            run.paths.len(),
+            run.entry
+        );
+    }
+
+    Ok(())
+}
+
+// print every detail of the run identified by `id`
+pub fn show(history_file: Option<PathBuf>, id: u64) -> Result<(), Error> {
+    let history_file = self::history_file(history_file)?;
+    let run = match self::load(&history_file)?
+        .into_iter()
+        .find(|run| run.id == id)
+    {
+        Some(run) => run,
+        None => {
+            return Err(Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No such run {}", id)
+            ))
+        }
+    };
+
+    println!("Run:      {}", run.id);
+    println!("Entry:    {}", run.entry);
+    println!("Start:    {}", self::format_timestamp(run.start));
+    println!("End:      {}", self::format_timestamp(run.end));
+    println!("Duration: {:.3}s", run.duration());
+    println!(
+        "Status:   {}",
+        if run.success { "success" } else { "failure" }
+    );
+    println!("Paths:");
+
+    for path in &run.paths {
+        println!("  {}", path);
+    }
+
+    for command in &run.commands {
+        println!();
+        println!("Command:   {}", command.command);
+
+        if let Some(error) = &command.error {
+            println!("Error:     {}", error);
+            continue;
+        }
+
+        match (command.exit_code, command.signal) {
+            (Some(code), _) => println!("Exit code: {}", code),
+            (None, Some(signal)) => println!("Signal:    {}", signal),
+            (None, None) => {}
+        }
+
+        println!("Duration:  {:.3}s", command.wall_time);
+        println!("Stdout:\n{}", command.stdout);
+        println!("Stderr:\n{}", command.stderr);
+    }
+
+    Ok(())
+}
+
+fn history_file(history_file: Option<PathBuf>) -> Result<PathBuf, Error> {
+    history_file
+        .ok_or_else(|| Error::new(std::io::ErrorKind::NotFound, "No `history-file` configured"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_limit() {
+        let output = "a".repeat(OUTPUT_LIMIT);
+
+        assert_eq!(CommandRun::truncate(output.as_bytes()), output);
+        assert_eq!(
+            CommandRun::truncate(format!("{}b", output).as_bytes()),
+            format!("{}[...]", output)
+        );
+    }
+
+    #[test]
+    fn truncate_char_boundary() {
+        // `é` takes two bytes, the limit falls between them
+        let output = format!("{}é", "a".repeat(OUTPUT_LIMIT - 1));
+
+        assert_eq!(
+            CommandRun::truncate(output.as_bytes()),
+            format!("{}[...]", "a".repeat(OUTPUT_LIMIT - 1))
+        );
+    }
+
+    #[test]
+    fn truncate_invalid_utf8() {
+        assert_eq!(CommandRun::truncate(&[0xff; 100]), "\u{fffd}".repeat(100));
+
+        let output = CommandRun::truncate(&[0xff; OUTPUT_LIMIT]);
+
+        assert!(output.ends_with("[...]"));
+        assert!(output.len() <= OUTPUT_LIMIT + "[...]".len());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{config::Config, error};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime}
+};
+
+lazy_static! {
+    // currently open log file, if any
+    static ref FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+    rotate_size: Option<u64>,
+    rotate_interval: Option<Duration>,
+    keep: usize,
+    // whether the last rotation failed, reported once
+    rotate_failed: bool
+}
+
+impl LogFile {
+    fn open(config: &Config, path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            size: file.metadata()?.len(),
+            file,
+            opened: SystemTime::now(),
+            rotate_size: config.log_rotate_size,
+            rotate_interval: config.log_rotate_interval,
+            keep: config.log_keep,
+            rotate_failed: false
+        })
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<(), Error> {
+        let oversized = self.rotate_size.is_some_and(|rotate_size| {
+            self.size > 0 && self.size + record.len() as u64 > rotate_size
+        });
+        let expired = self.rotate_interval.is_some_and(|rotate_interval| {
+            self.opened.elapsed().unwrap_or_default() >= rotate_interval
+        });
+
+        if oversized || expired {
+            match self.rotate() {
+                Ok(()) => self.rotate_failed = false,
+                Err(err) => {
+                    // keep writing to the current file, reporting the failure once; try again
+                    // after another `rotate_size` bytes or `rotate_interval`
+                    if !self.rotate_failed {
+                        eprintln!(
+                            "{}: Could not rotate log file {}: {}",
+                            clap::crate_name!(),
+                            self.path.display(),
+                            err
+                        );
+                    }
+
+                    self.rotate_failed = true;
+                    self.size = 0;
+                    self.opened = SystemTime::now();
+                }
+            }
+        }
+
+        self.file.write_all(record)?;
+        self.size += record.len() as u64;
+
+        Ok(())
+    }
+
+    // shift `path.N` to `path.N+1` (dropping those beyond `keep`), move `path` to
+    // `path.1` and start a new file
+    fn rotate(&mut self) -> Result<(), Error> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+
+        let _ = fs::remove_file(rotated(self.keep));
+
+        for n in (1..self.keep).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+
+        let result = if self.keep > 0 {
+            fs::rename(&self.path, rotated(1))
+        }
+        else {
+            fs::remove_file(&self.path)
+        };
+
+        // a missing `path` (e.g. removed by hand) is as good as rotated
+        match result {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = SystemTime::now();
+
+        Ok(())
+    }
+}
+
+// writer buffering each record and appending it to the current log file on
+// flush, so that records are never split across rotated files
+#[derive(Default)]
+pub(super) struct Writer {
+    buffer: Vec<u8>
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.buffer.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        // a record which cannot be written is dropped rather than failing the drain,
+        // which would take down logging as a whole
+        if let Some(log_file) = FILE.lock().unwrap().as_mut() {
+            let _ = log_file.write(&self.buffer);
+        }
+
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
+
+// (re)open the log file of `config`, closing the current one
+pub(super) fn reopen(config: &Config) -> Result<(), Error> {
+    let log_file = match &config.log_file {
+        Some(path) => Some(LogFile::open(config, path).map_err(|err| {
+            error::context(err, &format!("Could not open log file {}", path.display()))
+        })?),
+        None => None
+    };
+
+    *FILE.lock().unwrap() = log_file;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // log file `log` of a new test directory, holding `contents`
+    fn log_file(name: &str, contents: &str, keep: usize) -> LogFile {
+        let dir = std::env::temp_dir().join(format!("watchd-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("log");
+        fs::write(&path, contents).unwrap();
+
+        LogFile {
+            file: OpenOptions::new().append(true).open(&path).unwrap(),
+            path,
+            size: contents.len() as u64,
+            opened: SystemTime::now(),
+            rotate_size: Some(10),
+            rotate_interval: None,
+            keep,
+            rotate_failed: false
+        }
+    }
+
+    fn read(log_file: &LogFile, n: usize) -> Option<String> {
+        let path = match n {
+            0 => log_file.path.to_owned(),
+            n => PathBuf::from(format!("{}.{}", log_file.path.display(), n))
+        };
+
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn rotate_keep() {
+        let mut log_file = self::log_file("rotate-keep", "a\n", 2);
+
+        for record in &["b\n", "c\n", "d\n"] {
+            log_file.rotate().unwrap();
+            log_file.write(record.as_bytes()).unwrap();
+        }
+
+        assert_eq!(read(&log_file, 0).as_deref(), Some("d\n"));
+        assert_eq!(read(&log_file, 1).as_deref(), Some("c\n"));
+        assert_eq!(read(&log_file, 2).as_deref(), Some("b\n"));
+        assert_eq!(read(&log_file, 3), None);
+
+        fs::remove_dir_all(log_file.path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rotate_keep_none() {
+        let mut log_file = self::log_file("rotate-keep-none", "a\n", 0);
+
+        log_file.rotate().unwrap();
+        log_file.write(b"b\n").unwrap();
+
+        assert_eq!(read(&log_file, 0).as_deref(), Some("b\n"));
+        assert_eq!(read(&log_file, 1), None);
+
+        fs::remove_dir_all(log_file.path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rotate_missing() {
+        for keep in 0..2 {
+            let mut log_file = self::log_file("rotate-missing", "0123456789\n", keep);
+            fs::remove_file(&log_file.path).unwrap();
+
+            // the oversized record triggers a rotation, which recreates the file
+            log_file.write(b"a\n").unwrap();
+            log_file.write(b"b\n").unwrap();
+
+            assert_eq!(read(&log_file, 0).as_deref(), Some("a\nb\n"));
+            assert_eq!(read(&log_file, 1), None);
+            assert!(!log_file.rotate_failed);
+
+            fs::remove_dir_all(log_file.path.parent().unwrap()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use super::Fields;
+use crate::error;
+use slog::{Drain, Level, OwnedKVList, Record, KV};
+use std::{
+    fs::File,
+    io::{Error, Write},
+    mem,
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::UnixDatagram
+    },
+    ptr
+};
+
+static JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+// drain sending records to the systemd journal over its native protocol, with
+// every key-value as a journal field (e.g. `exit-code` as `EXIT_CODE`)
+pub(super) struct Journald {
+    socket: UnixDatagram
+}
+
+impl Journald {
+    pub fn new() -> Result<Self, Error> {
+        let socket = UnixDatagram::unbound()?;
+
+        socket.connect(JOURNAL_SOCKET).map_err(|err| {
+            error::context(
+                err,
+                &format!("Could not connect to journald at {}", JOURNAL_SOCKET)
+            )
+        })?;
+
+        Ok(Self { socket })
+    }
+
+    fn send(&self, datagram: &[u8]) -> Result<(), Error> {
+        match self.socket.send(datagram) {
+            Ok(_) => Ok(()),
+            // too large for a datagram: pass it in a sealed memfd instead, as
+            // sd_journal_send does
+            Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => {
+                self::send_memfd(&self.socket, datagram)
+            }
+            // the socket was recreated (e.g. journald restarted): reconnect once
+            Err(_) => {
+                self.socket.connect(JOURNAL_SOCKET)?;
+                self.socket.send(datagram).map(|_| ())
+            }
+        }
+    }
+}
+
+impl Drain for Journald {
+    type Err = Error;
+    type Ok = ();
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Error> {
+        let mut fields = Fields::default();
+
+        record.kv().serialize(record, &mut fields)?;
+        values.serialize(record, &mut fields)?;
+
+        let mut datagram = vec![];
+
+        self::append(&mut datagram, "MESSAGE", &fields.message(record));
+        self::append(
+            &mut datagram,
+            "PRIORITY",
+            &self::priority(record.level()).to_string()
+        );
+        self::append(&mut datagram, "SYSLOG_IDENTIFIER", "watchd");
+        self::append(&mut datagram, "EVENT", &record.msg().to_string());
+
+        for (key, value) in &fields.0 {
+            self::append(&mut datagram, &self::field_name(key), value);
+        }
+
+        self.send(&datagram)
+    }
+}
+
+// send `datagram` as the content of a sealed memfd, passed alone to `socket`
+fn send_memfd(socket: &UnixDatagram, datagram: &[u8]) -> Result<(), Error> {
+    let fd = unsafe {
+        libc::memfd_create(
+            b"watchd-journal\0".as_ptr() as *const libc::c_char,
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING
+        )
+    };
+
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    // closed once sent, the journal keeps its own descriptor
+    let mut file = unsafe { File::from_raw_fd(fd) };
+
+    file.write_all(datagram)?;
+
+    // the journal only reads memfds which can no longer change
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut control =
+        vec![0u8; unsafe { libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) } as usize];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control.len() as _;
+
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::c_int>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(header) as *mut libc::c_int, fd);
+
+        if libc::sendmsg(socket.as_raw_fd(), &message, 0) < 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// append a field in the binary-safe form: `NAME\n<64-bit LE length><value>\n`
+fn append(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    datagram.push(b'\n');
+    datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+// journal field names only allow uppercase letters, digits and underscores, and
+// shall not start with an underscore
+fn field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            }
+            else {
+                '_'
+            }
+        })
+        .collect();
+
+    name.trim_start_matches('_').to_owned()
+}
+
+pub(super) fn priority(level: Level) -> u8 {
+    match level {
+        Level::Critical => 2,
+        Level::Error => 3,
+        Level::Warning => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Seek, SeekFrom},
+        os::unix::io::RawFd
+    };
+
+    // receive the descriptor passed alone to `socket`
+    fn receive_fd(socket: &UnixDatagram) -> RawFd {
+        let mut control =
+            vec![0u8; unsafe { libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) } as usize];
+        let mut message: libc::msghdr = unsafe { mem::zeroed() };
+
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = control.len() as _;
+
+        unsafe {
+            assert!(libc::recvmsg(socket.as_raw_fd(), &mut message, 0) >= 0);
+
+            let header = libc::CMSG_FIRSTHDR(&message);
+
+            assert_eq!((*header).cmsg_type, libc::SCM_RIGHTS);
+
+            ptr::read_unaligned(libc::CMSG_DATA(header) as *const libc::c_int)
+        }
+    }
+
+    #[test]
+    fn memfd() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        let mut datagram = vec![];
+
+        self::append(&mut datagram, "MESSAGE", &"x".repeat(1 << 20));
+        send_memfd(&sender, &datagram).unwrap();
+
+        let fd = receive_fd(&receiver);
+        let seals = unsafe { libc::fcntl(fd, libc::F_GET_SEALS) };
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        let mut content = vec![];
+
+        // the descriptor shares the offset left at the end by the sender
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut content).unwrap();
+
+        assert!(content == datagram);
+        assert_ne!(seals & libc::F_SEAL_WRITE, 0);
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!(field_name("exit-code"), "EXIT_CODE");
+        assert_eq!(field_name("_entry.path"), "ENTRY_PATH");
+    }
+}
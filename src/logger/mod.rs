@@ -0,0 +1,396 @@
+mod file;
+mod journald;
+mod syslog;
+
+pub use self::syslog::Facility;
+
+use crate::config::{self, Config};
+use lazy_static::lazy_static;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use slog::{
+    Discard, Drain, Duplicate, FnValue, Fuse, Key, Level, Logger, Never, OwnedKVList, PushFnValue,
+    Record, Serializer
+};
+use slog_async::Async;
+use slog_json::Json;
+use slog_term::{FullFormat, PlainDecorator};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock
+    },
+    time::{Duration, SystemTime}
+};
+
+lazy_static! {
+    // logger writing to every drain, filtered by the level of each drain only
+    static ref BASE: Logger = self::new(&config::OPTS.read().unwrap());
+    // root logger
+    pub static ref ROOT: Logger = Logger::root(Filter::new(Scope::Global, BASE.clone()), o!());
+    // log levels, kept apart from `config::OPTS` so that they can be read while a
+    // configuration reload holds its lock
+    static ref LEVELS: RwLock<Levels> = RwLock::new(Levels::default());
+}
+
+struct Levels {
+    global: Level,
+    target: Option<Level>,
+    file: Option<Level>,
+    entries: HashMap<PathBuf, Level>
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Self {
+            global: Level::Info,
+            target: None,
+            file: None,
+            entries: HashMap::new()
+        }
+    }
+}
+
+// what a Filter's level applies to
+enum Scope {
+    Global,
+    Target,
+    File,
+    Entry(PathBuf)
+}
+
+impl Scope {
+    fn level(&self) -> Level {
+        let levels = LEVELS.read().unwrap();
+
+        match self {
+            Scope::Global => levels.global,
+            Scope::Target => levels.target.unwrap_or(Level::Trace),
+            Scope::File => levels.file.unwrap_or(Level::Trace),
+            Scope::Entry(entry_path) => *levels.entries.get(entry_path).unwrap_or(&levels.global)
+        }
+    }
+}
+
+// drain filtering records by the current level of its scope
+struct Filter<D> {
+    scope: Scope,
+    drain: D
+}
+
+impl<D> Filter<D> {
+    fn new(scope: Scope, drain: D) -> Self {
+        Self { scope, drain }
+    }
+}
+
+impl<D: Drain> Drain for Filter<D> {
+    type Err = D::Err;
+    type Ok = ();
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), D::Err> {
+        if record.level().is_at_least(self.scope.level()) {
+            self.drain.log(record, values)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        level.is_at_least(self.scope.level()) && self.drain.is_enabled(level)
+    }
+}
+
+// drain dropping the records `drain` fails to deliver (e.g. while the journal
+// is restarting), reporting the failure on stderr once until a record gets
+// through
+struct Report<D> {
+    target: &'static str,
+    drain: D,
+    failed: AtomicBool
+}
+
+impl<D> Report<D> {
+    fn new(target: &'static str, drain: D) -> Self {
+        Self {
+            target,
+            drain,
+            failed: AtomicBool::new(false)
+        }
+    }
+}
+
+impl<D: Drain<Ok = (), Err = std::io::Error>> Drain for Report<D> {
+    type Err = Never;
+    type Ok = ();
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        match self.drain.log(record, values) {
+            Ok(()) => self.failed.store(false, Ordering::Relaxed),
+            Err(err) => {
+                if !self.failed.swap(true, Ordering::Relaxed) {
+                    eprintln!(
+                        "{}: Could not log to {}: {}",
+                        clap::crate_name!(),
+                        self.target,
+                        err
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Json
+}
+
+impl Format {
+    pub fn from(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Stdout,
+    Journald,
+    Syslog
+}
+
+impl Target {
+    pub fn from(value: &str) -> Option<Self> {
+        match value {
+            "stdout" => Some(Target::Stdout),
+            "journald" => Some(Target::Journald),
+            "syslog" => Some(Target::Syslog),
+            _ => None
+        }
+    }
+}
+
+// parse a level name (`error`, `warning`, `info`, `debug`, `trace`...)
+pub fn level(value: &str) -> Option<Level> {
+    match value {
+        "critical" => Some(Level::Critical),
+        "error" => Some(Level::Error),
+        "warning" | "warn" => Some(Level::Warning),
+        "info" => Some(Level::Info),
+        "debug" => Some(Level::Debug),
+        "trace" => Some(Level::Trace),
+        _ => None
+    }
+}
+
+// deserialize an optional level name
+pub fn deserialize_level<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<Level>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+
+    match self::level(&value) {
+        Some(level) => Ok(Some(level)),
+        None => Err(D::Error::custom(format!("invalid log level {:?}", value)))
+    }
+}
+
+// deserialize an optional size in bytes, either as an integer or as a string
+// with a `K`, `M` or `G` suffix
+pub fn deserialize_size<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        String(String)
+    }
+
+    let value = match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => return Ok(Some(bytes)),
+        Size::String(value) => value
+    };
+
+    let (number, multiplier) = match value.chars().last() {
+        Some('K') | Some('k') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (&value[..], 1)
+    };
+
+    match number.trim().parse::<u64>() {
+        Ok(number) => Ok(Some(number * multiplier)),
+        Err(_) => Err(D::Error::custom(format!("invalid size {:?}", value)))
+    }
+}
+
+// deserialize an optional duration such as `1h` or `1day`
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<Duration>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+
+    match humantime::parse_duration(&value) {
+        Ok(duration) => Ok(Some(duration)),
+        Err(err) => Err(D::Error::custom(format!(
+            "invalid duration {:?}: {}",
+            value, err
+        )))
+    }
+}
+
+// apply the log levels of `config`
+pub fn set_levels(config: &Config) {
+    *LEVELS.write().unwrap() = Levels {
+        global: config.log_level,
+        target: config.log_target_level,
+        file: config.log_file_level,
+        entries: config
+            .entries
+            .iter()
+            .filter_map(|(entry_path, entry)| Some((entry_path.to_owned(), entry.log_level?)))
+            .collect()
+    };
+}
+
+// logger for the entry watching `entry_path`, filtered by the entry's level
+pub fn entry(entry_path: &PathBuf) -> Logger {
+    Logger::root(
+        Filter::new(Scope::Entry(entry_path.to_owned()), BASE.clone()),
+        o!("entry" => format!("{}", entry_path.display()))
+    )
+}
+
+// key-values of a record, rendered as strings
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl Fields {
+    // human readable message, as in the text format: `EVENT, key: value, ...`
+    fn message(&self, record: &Record) -> String {
+        let mut message = record.msg().to_string();
+
+        for (key, value) in &self.0 {
+            message.push_str(&format!(", {}: {}", key, value));
+        }
+
+        message
+    }
+}
+
+impl Serializer for Fields {
+    fn emit_arguments(&mut self, key: Key, value: &fmt::Arguments) -> slog::Result {
+        self.0.push((key.to_string(), value.to_string()));
+
+        Ok(())
+    }
+
+    // absent values are omitted
+    fn emit_none(&mut self, _key: Key) -> slog::Result {
+        Ok(())
+    }
+}
+
+// create an asynchronous drain formatting records as `format` into `io`
+fn drain<W: Write + Send + 'static>(io: W, format: Format) -> Fuse<Async> {
+    match format {
+        Format::Text => {
+            let decorator = PlainDecorator::new(io);
+            let drain = FullFormat::new(decorator).build().fuse();
+
+            Async::new(drain).build().fuse()
+        }
+        // one object per line, with the message as `event`
+        Format::Json => {
+            let drain = Json::new(io)
+                .add_key_value(o!(
+                    "time" => FnValue(|_| {
+                        humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+                    }),
+                    "level" => FnValue(|record| record.level().as_str()),
+                    "event" => PushFnValue(|record, serializer| serializer.emit(record.msg()))
+                ))
+                .set_flush(true)
+                .build()
+                .fuse();
+
+            Async::new(drain).build().fuse()
+        }
+    }
+}
+
+// create an asynchronous drain for `target`
+fn target_drain(target: Target, format: Format, facility: Facility) -> Fuse<Async> {
+    match target {
+        Target::Stdout => self::drain(std::io::stdout(), format),
+        // `connect` checked the target at startup, it may only have gone away since
+        Target::Journald => match journald::Journald::new() {
+            Ok(drain) => Async::new(Report::new("journald", drain)).build().fuse(),
+            Err(err) => self::discard(err)
+        },
+        Target::Syslog => match syslog::Syslog::new(facility) {
+            Ok(drain) => Async::new(Report::new("syslog", drain)).build().fuse(),
+            Err(err) => self::discard(err)
+        }
+    }
+}
+
+fn discard(err: std::io::Error) -> Fuse<Async> {
+    eprintln!("{}: {}", clap::crate_name!(), err);
+
+    Async::new(Discard).build().fuse()
+}
+
+// connect to the log target of `config` before logging anything, failing if it
+// is unreachable rather than dropping every record
+pub fn connect(config: &Config) -> Result<(), std::io::Error> {
+    match config.log_target {
+        Target::Stdout => Ok(()),
+        Target::Journald => journald::Journald::new().map(|_| ()),
+        Target::Syslog => syslog::Syslog::new(config.syslog_facility).map(|_| ())
+    }
+}
+
+// create an asynchronous drain for the log file, writing nowhere until one is
+// opened
+fn file_drain(format: Format) -> Fuse<Async> {
+    self::drain(file::Writer::default(), format)
+}
+
+// (re)open the log file of `config`, e.g. after it was moved by logrotate
+pub fn reopen(config: &Config) -> Result<(), std::io::Error> {
+    file::reopen(config)
+}
+
+fn new(config: &Config) -> Logger {
+    self::set_levels(config);
+
+    // always log to `log_target`
+    let target_filter = Filter::new(
+        Scope::Target,
+        self::target_drain(config.log_target, config.log_format, config.syslog_facility)
+    );
+
+    // duplicate logs to `log_file`, once opened with `reopen`
+    Logger::root(
+        Duplicate::new(
+            target_filter,
+            Filter::new(Scope::File, self::file_drain(config.log_format))
+        )
+        .fuse(),
+        o!()
+    )
+}
@@ -0,0 +1,117 @@
+use super::{journald, Fields};
+use crate::error;
+use slog::{Drain, OwnedKVList, Record, KV};
+use std::{io::Error, os::unix::net::UnixDatagram};
+
+static SYSLOG_SOCKET: &str = "/dev/log";
+// longest message sent once a datagram turned out too large, the default limit
+// of rsyslog
+const MAX_MESSAGE: usize = 8192;
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    Authpriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23
+}
+
+// drain sending records to the local syslog daemon
+pub(super) struct Syslog {
+    socket: UnixDatagram,
+    facility: Facility
+}
+
+impl Syslog {
+    pub fn new(facility: Facility) -> Result<Self, Error> {
+        let socket = UnixDatagram::unbound()?;
+
+        socket.connect(SYSLOG_SOCKET).map_err(|err| {
+            error::context(
+                err,
+                &format!("Could not connect to syslog at {}", SYSLOG_SOCKET)
+            )
+        })?;
+
+        Ok(Self { socket, facility })
+    }
+
+    fn send(&self, message: &str) -> Result<(), Error> {
+        match self.socket.send(message.as_bytes()) {
+            Ok(_) => Ok(()),
+            // too large for a datagram: cut it down
+            Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => self
+                .socket
+                .send(self::truncate(message, MAX_MESSAGE).as_bytes())
+                .map(|_| ()),
+            // the socket was recreated (e.g. the syslog daemon restarted):
+            // reconnect once
+            Err(_) => {
+                self.socket.connect(SYSLOG_SOCKET)?;
+                self.socket.send(message.as_bytes()).map(|_| ())
+            }
+        }
+    }
+}
+
+impl Drain for Syslog {
+    type Err = Error;
+    type Ok = ();
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Error> {
+        let mut fields = Fields::default();
+
+        record.kv().serialize(record, &mut fields)?;
+        values.serialize(record, &mut fields)?;
+
+        // `<PRI>TAG[PID]: MESSAGE`, the local daemon adds timestamp and hostname
+        let message = format!(
+            "<{}>watchd[{}]: {}",
+            (self.facility as u8) * 8 + journald::priority(record.level()),
+            std::process::id(),
+            fields.message(record)
+        );
+
+        self.send(&message)
+    }
+}
+
+// cut `message` to at most `limit` bytes, at a char boundary
+fn truncate(message: &str, limit: usize) -> &str {
+    let index = (0..=limit.min(message.len()))
+        .rev()
+        .find(|&index| message.is_char_boundary(index))
+        .unwrap_or(0);
+
+    &message[..index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_boundary() {
+        assert_eq!(truncate("short", MAX_MESSAGE), "short");
+        assert_eq!(truncate("abcdef", 4), "abcd");
+        // `é` takes two bytes, the cut falls before it
+        assert_eq!(truncate("abcé", 4), "abc");
+    }
+}
@@ -5,20 +5,135 @@ extern crate slog;
 #[macro_use]
 extern crate lazy_static;
 
+mod autoreload;
 mod cli;
 mod config;
+mod control;
+mod error;
+mod history;
 mod logger;
+mod metrics;
 mod signal;
+mod state;
+mod status;
+mod stream;
+mod systemd;
 mod watcher;
 
-use std::io::Error;
+use error::Error;
+
+fn main() {
+    if let Err(err) = self::run(cli::Options::load()) {
+        eprintln!("{}: {}", clap::crate_name!(), err);
+
+        std::process::exit(err.exit_code());
+    }
+}
+
+fn run(options: cli::Options) -> Result<(), Error> {
+    // load the configuration, unless the subcommand does without it
+    let config_needed = match &options.subcommand {
+        // `check` validates it by itself, `history` and `show-run` only read
+        // `history-file`, `ctl` and `tail` only `control-socket`
+        Some(cli::Subcommand::Check)
+        | Some(cli::Subcommand::History { .. })
+        | Some(cli::Subcommand::ShowRun { .. })
+        | Some(cli::Subcommand::Ctl { .. })
+        | Some(cli::Subcommand::Tail { .. }) => false,
+        _ => true
+    };
+
+    if config_needed {
+        config::init(options.to_owned())?;
+    }
+
+    // query subcommands
+    match options.subcommand.to_owned() {
+        Some(cli::Subcommand::History {
+            entry,
+            failed,
+            since
+        }) => {
+            let history_file = config::history_file(&options)?;
+
+            return Ok(history::list(
+                history_file,
+                entry.as_deref(),
+                failed,
+                since
+            )?);
+        }
+        Some(cli::Subcommand::ShowRun { id }) => {
+            let history_file = config::history_file(&options)?;
+
+            return Ok(history::show(history_file, id)?);
+        }
+        Some(cli::Subcommand::Ctl {
+            socket,
+            json,
+            request
+        }) => {
+            let socket = match socket {
+                Some(socket) => Some(socket),
+                None => config::control_socket(&options)?
+            };
+
+            return Ok(control::request(socket, json, request)?);
+        }
+        Some(cli::Subcommand::Tail {
+            socket,
+            json,
+            entry
+        }) => {
+            let socket = match socket {
+                Some(socket) => Some(socket),
+                None => config::control_socket(&options)?
+            };
+
+            return Ok(stream::tail(socket, json, entry)?);
+        }
+        Some(cli::Subcommand::Check) => return self::check(options),
+        None => {}
+    }
+
+    // reach the log target and open the log file before logging anything
+    {
+        let config = config::OPTS.read().unwrap();
+
+        logger::connect(&config)?;
+        logger::reopen(&config)?;
+    }
 
-fn main() -> Result<(), Error> {
     info!(
         logger::ROOT, "PROGRAM";
         "status" => "started"
     );
 
     // main loop signal handling
-    signal::Handler::new().handle()
+    Ok(signal::Handler::new().handle()?)
+}
+
+// validate the configuration without starting the daemon, exiting with the
+// status of an invalid configuration on problems
+fn check(options: cli::Options) -> Result<(), Error> {
+    let config_file = options.config_file.to_owned();
+
+    match config::Config::check(options) {
+        Ok(config) => {
+            println!(
+                "{}: OK ({} entries)",
+                config_file.display(),
+                config.entries.len()
+            );
+
+            Ok(())
+        }
+        Err(problems) => {
+            for problem in &problems {
+                eprintln!("{}", problem);
+            }
+
+            std::process::exit(Error::Config(config::Error::Problems(problems)).exit_code());
+        }
+    }
 }
@@ -0,0 +1,138 @@
+use crate::logger;
+use prometheus::{Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder};
+use std::{
+    io::{BufRead, BufReader, Error, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration
+};
+
+// time allowed to read the request from, or write the response to, a client
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    pub static ref EVENTS: IntCounterVec = prometheus::register_int_counter_vec!(
+        "watchd_events_total",
+        "Filesystem events received",
+        &["entry", "operation"]
+    )
+    .unwrap();
+    pub static ref EVENTS_EXCLUDED: IntCounterVec = prometheus::register_int_counter_vec!(
+        "watchd_events_excluded_total",
+        "Filesystem events ignored by an `exclude` pattern",
+        &["entry", "operation"]
+    )
+    .unwrap();
+    pub static ref RUNS_STARTED: IntCounterVec =
+        prometheus::register_int_counter_vec!("watchd_runs_started_total", "Runs started", &[
+            "entry"
+        ])
+        .unwrap();
+    pub static ref RUNS: IntCounterVec = prometheus::register_int_counter_vec!(
+        "watchd_runs_total",
+        "Runs finished, by result (success or failure)",
+        &["entry", "result"]
+    )
+    .unwrap();
+    pub static ref COMMAND_DURATION: HistogramVec = prometheus::register_histogram_vec!(
+        "watchd_command_duration_seconds",
+        "Wall time of commands",
+        &["entry"],
+        vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0]
+    )
+    .unwrap();
+    pub static ref DEBOUNCE_LATENCY: HistogramVec = prometheus::register_histogram_vec!(
+        "watchd_debounce_latency_seconds",
+        "Time from the first event of a batch to the start of its run",
+        &["entry"],
+        vec![0.01, 0.05, 0.1, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap();
+    pub static ref QUEUE_DEPTH: IntGaugeVec = prometheus::register_int_gauge_vec!(
+        "watchd_queue_depth",
+        "Events waiting to be received by the handler",
+        &["entry"]
+    )
+    .unwrap();
+    pub static ref WATCHES: IntGauge =
+        prometheus::register_int_gauge!("watchd_active_watches", "Active filesystem watches")
+            .unwrap();
+}
+
+// serve `/metrics` at `address`
+pub fn spawn(address: SocketAddr) -> Result<(), Error> {
+    let listener = TcpListener::bind(address)?;
+
+    thread::Builder::new()
+        .name("metrics".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                // a slow client only holds up its own connection
+                let result = stream.and_then(|stream| {
+                    thread::Builder::new()
+                        .name("metrics-client".to_owned())
+                        .spawn(move || {
+                            if let Err(err) = self::serve(stream) {
+                                self::log_error(&err);
+                            }
+                        })
+                        .map(|_| ())
+                });
+
+                if let Err(err) = result {
+                    self::log_error(&err);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn serve(mut stream: TcpStream) -> Result<(), Error> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    reader.read_line(&mut request_line)?;
+
+    // skip the request headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, content_type, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let encoder = TextEncoder::new();
+            let mut body = Vec::new();
+            encoder
+                .encode(&prometheus::gather(), &mut body)
+                .map_err(Error::other)?;
+
+            ("200 OK", encoder.format_type().to_owned(), body)
+        }
+        _ => (
+            "404 Not Found",
+            "text/plain".to_owned(),
+            b"Not Found\n".to_vec()
+        )
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
+
+fn log_error(err: &Error) {
+    error!(
+        logger::ROOT, "METRICS";
+        "error" => true,
+        "message" => err.to_string()
+    );
+}
@@ -1,53 +1,171 @@
-use crate::{config, logger, watcher::Watcher};
-use signal_hook::{iterator::Signals, SIGHUP, SIGINT, SIGQUIT, SIGTERM};
-use std::io::Error;
+use crate::{
+    autoreload, config, control, error, logger, metrics, state, status, systemd, watcher::Watcher
+};
+use regex::bytes::Regex;
+use signal_hook::{iterator::Signals, SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1};
+use std::{
+    io::Error,
+    os::raw::c_int,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant}
+};
+
+// interval between checks of the watcher and handler threads
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(1);
+
+// events handled by the main loop
+pub enum Event {
+    Signal(c_int),
+    Control(control::Request, Sender<control::Response>),
+    ConfigChanged
+}
 
 pub struct Handler {
-    watchers: Vec<Watcher>
+    watchers: Vec<Watcher>,
+    config_watcher: Option<autoreload::ConfigWatcher>
 }
 
 impl Handler {
     pub fn new() -> Self {
+        for entry_path in self::sorted(config::OPTS.read().unwrap().entries.keys()) {
+            self::log_entry(&entry_path);
+        }
+
         // for each entry, instantiate a Watcher
         let watchers: Vec<Watcher> = watchers();
 
-        if config::OPTS.read().unwrap().verbose {
-            info!(
-                logger::ROOT, "BOOT";
-                "watchers" => "started",
-                "handlers" => "started"
-            );
-        }
+        debug!(
+            logger::ROOT, "BOOT";
+            "watchers" => "started",
+            "handlers" => "started"
+        );
 
-        Self { watchers }
+        Self {
+            watchers,
+            config_watcher: None
+        }
     }
 
     pub fn handle(&mut self) -> Result<(), Error> {
-        let signals = Signals::new(&[
+        let signals = Signals::new([
             SIGHUP,  // 1
             SIGINT,  // 2
             SIGQUIT, // 3
-            SIGTERM, // 15
+            SIGUSR1, // 10
+            SIGTERM  // 15
         ])?;
 
+        let (events_tx, events_rx) = channel();
+
+        // forward signals to the main loop
+        let signals_tx = events_tx.clone();
+
+        thread::Builder::new()
+            .name("signals".to_owned())
+            .spawn(move || {
+                for signal in signals.forever() {
+                    if signals_tx.send(Event::Signal(signal)).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        // forward control requests to the main loop
+        let control_socket = config::OPTS.read().unwrap().control_socket.to_owned();
+
+        if let Some(control_socket) = &control_socket {
+            control::spawn(control_socket, events_tx.clone()).map_err(|err| {
+                error::context(
+                    err,
+                    &format!("Could not bind control socket {}", control_socket.display())
+                )
+            })?;
+        }
+
+        // forward configuration file changes to the main loop
+        match autoreload::ConfigWatcher::new(events_tx) {
+            Ok(config_watcher) => self.config_watcher = Some(config_watcher),
+            Err(err) => {
+                error!(
+                    logger::ROOT, "AUTORELOAD";
+                    "error" => true,
+                    "message" => err.to_string()
+                );
+            }
+        }
+
+        // serve metrics
+        if let Some(metrics_address) = config::OPTS.read().unwrap().metrics_address {
+            metrics::spawn(metrics_address).map_err(|err| {
+                error::context(
+                    err,
+                    &format!("Could not serve metrics at {}", metrics_address)
+                )
+            })?;
+        }
+
+        // write the status file
+        status::spawn()?;
+
+        // notify the service manager once every watch is registered
+        self.ready();
+
+        let watchdog = systemd::watchdog();
+        let mut watchdog_ping = Instant::now();
+
         // main loop
-        // match signals as they come
-        for signal in signals.forever() {
-            match signal {
+        // match events as they come, supervising the watchers and pinging the
+        // watchdog in between
+        loop {
+            let timeout = match watchdog {
+                Some(interval) => std::cmp::min(
+                    SUPERVISE_INTERVAL,
+                    interval.saturating_sub(watchdog_ping.elapsed())
+                ),
+                None => SUPERVISE_INTERVAL
+            };
+
+            let event = match events_rx.recv_timeout(timeout) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break
+            };
+
+            for watcher in self.watchers.iter_mut() {
+                watcher.supervise();
+            }
+
+            // the ping only vouches for the main loop: dead threads are restarted by
+            // `supervise` above, and given up on without stopping the other entries
+            if let Some(interval) = watchdog {
+                if watchdog_ping.elapsed() >= interval {
+                    systemd::notify("WATCHDOG=1");
+                    watchdog_ping = Instant::now();
+                }
+            }
+
+            let event = match event {
+                Some(event) => event,
+                None => continue
+            };
+
+            match event {
                 // exit program
-                SIGTERM | SIGINT | SIGQUIT => {
-                    if config::OPTS.read().unwrap().verbose {
-                        info!(
-                            logger::ROOT, "PROGRAM";
-                            "status" => "exiting",
-                            "signal" => signal
-                        );
-                    }
+                Event::Signal(signal @ SIGTERM)
+                | Event::Signal(signal @ SIGINT)
+                | Event::Signal(signal @ SIGQUIT) => {
+                    debug!(
+                        logger::ROOT, "PROGRAM";
+                        "status" => "exiting",
+                        "signal" => signal
+                    );
 
                     break;
                 }
                 // reload configuration
-                SIGHUP => {
+                Event::Signal(signal @ SIGHUP) => {
                     info!(
                         logger::ROOT, "RELOAD";
                         "status" => "starting",
@@ -55,12 +173,41 @@ impl Handler {
                     );
 
                     // handle reload
-                    self.reload();
+                    let _ = self.reload();
+                }
+                // reopen log file
+                Event::Signal(signal @ SIGUSR1) => {
+                    info!(
+                        logger::ROOT, "REOPEN";
+                        "signal" => signal
+                    );
+
+                    self::reopen();
+                }
+                Event::Signal(_) => unreachable!(),
+                // control request
+                Event::Control(request, response_tx) => {
+                    let _ = response_tx.send(self.control(request));
+                }
+                // configuration file changed
+                Event::ConfigChanged => {
+                    info!(
+                        logger::ROOT, "RELOAD";
+                        "status" => "starting",
+                        "trigger" => "auto"
+                    );
+
+                    let _ = self.reload();
                 }
-                _ => unreachable!()
             }
         }
 
+        systemd::notify("STOPPING=1");
+
+        if let Some(control_socket) = &control_socket {
+            let _ = std::fs::remove_file(control_socket);
+        }
+
         info!(
             logger::ROOT, "PROGRAM";
             "status" => "exited"
@@ -69,34 +216,266 @@ impl Handler {
         Ok(())
     }
 
-    fn reload(&mut self) {
-        // terminate watchers; this is required to acquire a WriteLock
-        for watcher in &self.watchers {
-            watcher.terminate();
-        }
+    fn control(&mut self, request: control::Request) -> control::Response {
+        match request {
+            control::Request::Status => {
+                let mut entry_paths: Vec<_> = self
+                    .watchers
+                    .iter()
+                    .map(|watcher| &watcher.entry_path)
+                    .collect();
+                entry_paths.sort();
 
-        // acquire WriteLock
-        if config::OPTS.write().unwrap().reload().is_ok() {
-            info!(
-                logger::ROOT, "RELOAD";
-                "status" => "complete"
-            );
+                control::Response {
+                    entries: Some(
+                        entry_paths
+                            .into_iter()
+                            .map(|entry_path| control::EntryStatus {
+                                path: entry_path.display().to_string(),
+                                state: state::get(entry_path)
+                            })
+                            .collect()
+                    ),
+                    ..control::Response::ok()
+                }
+            }
+            control::Request::Trigger { entry } => match self.watcher(&entry) {
+                Some(watcher) => {
+                    watcher.trigger();
 
-            // deploy new watchers
-            self.watchers = watchers();
-        }
-        else {
-            error!(
-                logger::ROOT, "RELOAD";
-                "status" => "failed"
-            );
+                    control::Response::ok()
+                }
+                None => self::no_such_entry(&entry)
+            },
+            control::Request::Pause { entry } | control::Request::Resume { entry }
+                if self.watcher(&entry).is_none() =>
+            {
+                self::no_such_entry(&entry)
+            }
+            control::Request::Pause { entry } => {
+                state::update(&entry, |state| state.paused = true);
 
-            // redeploy the previous configuration's watchers
-            for watcher in self.watchers.iter_mut() {
-                watcher.restart();
+                control::Response::ok()
             }
+            control::Request::Resume { entry } => {
+                state::update(&entry, |state| state.paused = false);
+
+                control::Response::ok()
+            }
+            control::Request::Reload => match self.reload() {
+                Ok(()) => control::Response::ok(),
+                Err(err) => control::Response::error(err)
+            },
+            control::Request::LastRun { entry } => match self.watcher(&entry) {
+                Some(_) => match state::get(&entry).last_run {
+                    Some(run) => control::Response {
+                        run: Some(run),
+                        ..control::Response::ok()
+                    },
+                    None => control::Response::error("No run yet")
+                },
+                None => self::no_such_entry(&entry)
+            },
+            // handled by the control server itself
+            control::Request::Subscribe { .. } => unreachable!()
         }
     }
+
+    // send `READY=1` once every watcher registered its watch, along with a
+    // summary of the entries
+    fn ready(&self) {
+        let failed = self
+            .watchers
+            .iter()
+            .filter(|watcher| !watcher.registered())
+            .count();
+
+        systemd::notify(&format!(
+            "READY=1\nSTATUS=Watching {} entries{}",
+            self.watchers.len() - failed,
+            if failed > 0 {
+                format!(", {} failed", failed)
+            }
+            else {
+                String::new()
+            }
+        ));
+    }
+
+    fn watcher(&self, entry_path: &Path) -> Option<&Watcher> {
+        self.watchers
+            .iter()
+            .find(|watcher| watcher.entry_path == entry_path)
+    }
+
+    fn reload(&mut self) -> Result<(), config::Error> {
+        systemd::reloading();
+
+        // parse and validate the new configuration before touching the watchers
+        let result = config::OPTS.read().unwrap().reload();
+
+        let result = match result {
+            Ok(config) => {
+                let (added, removed, changed, rewatched) = {
+                    let current = config::OPTS.read().unwrap();
+
+                    (
+                        self::sorted(
+                            config
+                                .entries
+                                .keys()
+                                .filter(|entry_path| !current.entries.contains_key(*entry_path))
+                        ),
+                        self::sorted(
+                            current
+                                .entries
+                                .keys()
+                                .filter(|entry_path| !config.entries.contains_key(*entry_path))
+                        ),
+                        self::sorted(config.entries.iter().filter_map(|(entry_path, entry)| {
+                            current
+                                .entries
+                                .get(entry_path)
+                                .filter(|current_entry| *current_entry != entry)
+                                .map(|_| entry_path)
+                        })),
+                        self::sorted(config.entries.iter().filter_map(|(entry_path, entry)| {
+                            current
+                                .entries
+                                .get(entry_path)
+                                .filter(|current_entry| current_entry.recursive != entry.recursive)
+                                .map(|_| entry_path)
+                        }))
+                    )
+                };
+
+                // stop the watchers of removed entries before they disappear from the
+                // configuration
+                let (removed_watchers, watchers): (Vec<Watcher>, Vec<Watcher>) = self
+                    .watchers
+                    .drain(..)
+                    .partition(|watcher| removed.contains(&watcher.entry_path));
+                self.watchers = watchers;
+
+                for watcher in removed_watchers {
+                    watcher.stop();
+                }
+
+                // acquire WriteLock; changes to the remaining entries apply in place
+                *config::OPTS.write().unwrap() = config;
+
+                // apply the new log levels and log file
+                logger::set_levels(&config::OPTS.read().unwrap());
+                self::reopen();
+
+                // forget removed entries
+                state::retain(config::OPTS.read().unwrap().entries.keys());
+
+                for watcher in self.watchers.iter_mut() {
+                    if rewatched.contains(&watcher.entry_path) {
+                        // the watch mode is set at registration
+                        watcher.rewatch();
+                    }
+                    else if !watcher.alive() {
+                        // give crashed entries a fresh start
+                        watcher.respawn();
+                    }
+                }
+
+                // deploy the watchers of added entries
+                for entry_path in &added {
+                    self.watchers.push(Watcher::new(entry_path.to_owned()));
+                }
+
+                // follow the configuration files
+                if let Some(config_watcher) = self.config_watcher.as_mut() {
+                    config_watcher.update();
+                }
+
+                for entry_path in added.iter().chain(&changed) {
+                    self::log_entry(entry_path);
+                }
+
+                info!(
+                    logger::ROOT, "RELOAD";
+                    "status" => "complete",
+                    "added" => self::list(&added),
+                    "removed" => self::list(&removed),
+                    "changed" => self::list(&changed)
+                );
+
+                Ok(())
+            }
+            Err(err) => {
+                // the current configuration and watchers are left untouched
+                error!(
+                    logger::ROOT, "RELOAD";
+                    "status" => "failed",
+                    "reason" => err.to_string()
+                );
+
+                Err(err)
+            }
+        };
+
+        self.ready();
+        status::reloaded(&result);
+
+        result
+    }
+}
+
+fn no_such_entry(entry_path: &Path) -> control::Response {
+    control::Response::error(format!("No such entry {}", entry_path.display()))
+}
+
+fn reopen() {
+    if let Err(err) = logger::reopen(&config::OPTS.read().unwrap()) {
+        error!(
+            logger::ROOT, "REOPEN";
+            "error" => true,
+            "message" => err.to_string()
+        );
+    }
+}
+
+// log the effective options of an entry, with those of its template and
+// `defaults`
+fn log_entry(entry_path: &PathBuf) {
+    let config = config::OPTS.read().unwrap();
+    let entry = &config.entries[entry_path];
+
+    debug!(
+        logger::entry(entry_path), "ENTRY";
+        "recursive" => entry.recursive,
+        "delay" => entry.delay,
+        "excludes" => format!(
+            "{:?}",
+            entry.excludes.iter().map(Regex::as_str).collect::<Vec<_>>()
+        ),
+        "commands" => format!("{:?}", entry.commands),
+        "output-dir" => entry
+            .output_dir
+            .as_ref()
+            .map(|output_dir| output_dir.display().to_string()),
+        "keep-runs" => entry.keep_runs,
+        "log-level" => entry.log_level.map(|log_level| log_level.as_str().to_lowercase())
+    );
+}
+
+fn sorted<'a, I: Iterator<Item = &'a PathBuf>>(entry_paths: I) -> Vec<PathBuf> {
+    let mut entry_paths: Vec<PathBuf> = entry_paths.cloned().collect();
+    entry_paths.sort();
+
+    entry_paths
+}
+
+fn list(entry_paths: &[PathBuf]) -> String {
+    entry_paths
+        .iter()
+        .map(|entry_path| entry_path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 fn watchers() -> Vec<Watcher> {
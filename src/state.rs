@@ -0,0 +1,65 @@
+use crate::history;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime
+};
+
+lazy_static! {
+    // runtime state of every entry, surviving watcher restarts
+    static ref ENTRIES: RwLock<HashMap<PathBuf, Entry>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Entry {
+    pub paused: bool,
+    pub running: bool,
+    // why the entry is not being watched
+    pub error: Option<String>,
+    // seconds since the UNIX epoch
+    pub last_event: Option<f64>,
+    pub last_run: Option<history::Run>
+}
+
+// snapshot of the state of `entry_path`
+pub fn get(entry_path: &Path) -> Entry {
+    ENTRIES
+        .read()
+        .unwrap()
+        .get(entry_path)
+        .cloned()
+        .unwrap_or_default()
+}
+
+// update the state of `entry_path`
+pub fn update<F: FnOnce(&mut Entry)>(entry_path: &Path, update: F) {
+    update(
+        ENTRIES
+            .write()
+            .unwrap()
+            .entry(entry_path.to_owned())
+            .or_default()
+    );
+}
+
+pub fn paused(entry_path: &Path) -> bool {
+    self::get(entry_path).paused
+}
+
+pub fn event(entry_path: &Path) {
+    self::update(entry_path, |entry| {
+        entry.last_event = Some(history::timestamp(SystemTime::now()))
+    });
+}
+
+// forget the state of entries which are not in `entry_paths`
+pub fn retain<'a, I: IntoIterator<Item = &'a PathBuf>>(entry_paths: I) {
+    let entry_paths: Vec<&PathBuf> = entry_paths.into_iter().collect();
+
+    ENTRIES
+        .write()
+        .unwrap()
+        .retain(|entry_path, _| entry_paths.contains(&entry_path));
+}
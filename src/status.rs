@@ -0,0 +1,158 @@
+use crate::{config, history, logger, state};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::SystemTime
+};
+
+lazy_static! {
+    // seconds since the UNIX epoch
+    static ref STARTED: f64 = history::timestamp(SystemTime::now());
+    // outcome of the last configuration reload, if any
+    static ref LAST_RELOAD: Mutex<Option<Reload>> = Mutex::new(None);
+    // serialize writers of the status file
+    static ref WRITE: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+struct Reload {
+    time: f64,
+    success: bool,
+    error: Option<String>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Status {
+    pid: u32,
+    started: f64,
+    updated: f64,
+    config_file: String,
+    config_mtime: Option<f64>,
+    config_sha256: Option<String>,
+    last_reload: Option<Reload>,
+    entries: Vec<Entry>
+}
+
+#[derive(Serialize)]
+struct Entry {
+    path: String,
+    // one of watching, paused, running or error
+    status: &'static str,
+    #[serde(flatten)]
+    state: state::Entry
+}
+
+// record the start time of the daemon and write the status file every
+// `status-interval`
+pub fn spawn() -> Result<(), Error> {
+    lazy_static::initialize(&STARTED);
+
+    self::write();
+
+    thread::Builder::new()
+        .name("status".to_owned())
+        .spawn(|| loop {
+            let status_interval = config::OPTS.read().unwrap().status_interval;
+
+            thread::sleep(status_interval);
+
+            self::write();
+        })?;
+
+    Ok(())
+}
+
+// record the outcome of a configuration reload
+pub fn reloaded<E: ToString>(result: &Result<(), E>) {
+    *LAST_RELOAD.lock().unwrap() = Some(Reload {
+        time: history::timestamp(SystemTime::now()),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(ToString::to_string)
+    });
+
+    self::write();
+}
+
+// replace the status file, if any, logging errors
+pub fn write() {
+    let _write = WRITE.lock().unwrap();
+
+    let (status_file, status) = {
+        let config = config::OPTS.read().unwrap();
+
+        match &config.status_file {
+            Some(status_file) => (status_file.to_owned(), self::status(&config)),
+            None => return
+        }
+    };
+
+    if let Err(err) = self::replace(&status_file, &status) {
+        error!(
+            logger::ROOT, "STATUS";
+            "error" => true,
+            "path" => status_file.display(),
+            "message" => err.to_string()
+        );
+    }
+}
+
+fn status(config: &config::Config) -> Status {
+    let config_file = config.config_file();
+
+    let mut entry_paths: Vec<&PathBuf> = config.entries.keys().collect();
+    entry_paths.sort();
+
+    Status {
+        pid: std::process::id(),
+        started: *STARTED,
+        updated: history::timestamp(SystemTime::now()),
+        config_file: config_file.display().to_string(),
+        config_mtime: fs::metadata(config_file)
+            .and_then(|metadata| metadata.modified())
+            .map(history::timestamp)
+            .ok(),
+        config_sha256: fs::read(config_file)
+            .map(|contents| format!("{:x}", Sha256::digest(contents)))
+            .ok(),
+        last_reload: LAST_RELOAD.lock().unwrap().clone(),
+        entries: entry_paths
+            .into_iter()
+            .map(|entry_path| {
+                let state = state::get(entry_path);
+
+                Entry {
+                    path: entry_path.display().to_string(),
+                    status: if state.error.is_some() {
+                        "error"
+                    }
+                    else if state.running {
+                        "running"
+                    }
+                    else if state.paused {
+                        "paused"
+                    }
+                    else {
+                        "watching"
+                    },
+                    state
+                }
+            })
+            .collect()
+    }
+}
+
+// write `status` next to `status_file` and move it in place, so that readers
+// never see a partial file
+fn replace(status_file: &Path, status: &Status) -> Result<(), Error> {
+    let mut tmp_file = status_file.as_os_str().to_owned();
+    tmp_file.push(".tmp");
+
+    fs::write(&tmp_file, serde_json::to_vec_pretty(status)?)?;
+    fs::rename(&tmp_file, status_file)
+}
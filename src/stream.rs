@@ -0,0 +1,234 @@
+use crate::{control, history, logger};
+use std::{
+    io::{BufRead, BufReader, Error, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Mutex
+    },
+    time::{Duration, SystemTime}
+};
+
+// events queued for a subscriber before it is dropped for not keeping up
+const SUBSCRIBER_BUFFER: usize = 1024;
+// time allowed to write an event to a subscriber before it is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    // clients following the live event stream
+    static ref SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+}
+
+// identifier of the next subscriber
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Subscriber {
+    id: u64,
+    entry: Option<PathBuf>,
+    tx: SyncSender<String>
+}
+
+// live event, one JSON object per line
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Record {
+    // seconds since the UNIX epoch
+    pub time: f64,
+    pub entry: String,
+    #[serde(flatten)]
+    pub event: Event
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    // filesystem event seen by the watcher, `exclude` being the matching pattern
+    // of an ignored one
+    Fs {
+        operation: String,
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        path_to: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        exclude: Option<String>
+    },
+    // first event of a batch, the handler waits `delay` seconds for more
+    DebounceStarted {
+        delay: f64
+    },
+    RunStarted {
+        run: u64,
+        paths: Vec<String>
+    },
+    RunFinished {
+        run: u64,
+        success: bool,
+        wall_time: f64
+    }
+}
+
+// publish `event` of `entry_path` to every interested subscriber
+pub fn publish(entry_path: &Path, event: Event) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+
+    if subscribers.is_empty() {
+        return;
+    }
+
+    let record = Record {
+        time: history::timestamp(SystemTime::now()),
+        entry: entry_path.display().to_string(),
+        event
+    };
+
+    let mut line = match serde_json::to_string(&record) {
+        Ok(line) => line,
+        Err(_) => return
+    };
+    line.push('\n');
+
+    // forget subscribers which went away, or fell `SUBSCRIBER_BUFFER` events
+    // behind
+    subscribers.retain(|subscriber| {
+        if subscriber
+            .entry
+            .as_ref()
+            .is_some_and(|entry| entry != entry_path)
+        {
+            return true;
+        }
+
+        match subscriber.tx.try_send(line.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                error!(
+                    logger::ROOT, "CONTROL";
+                    "command" => "subscribe",
+                    "error" => true,
+                    "message" => "Subscriber is not reading events, dropped"
+                );
+
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false
+        }
+    });
+}
+
+// stream the events of `entry` (or of every entry) to `stream` until the
+// client goes away or stops reading
+pub fn subscribe(mut stream: UnixStream, entry: Option<PathBuf>) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = sync_channel(SUBSCRIBER_BUFFER);
+
+    if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+        return;
+    }
+
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .push(Subscriber { id, entry, tx });
+
+    loop {
+        let line = match rx.recv() {
+            Ok(line) => line,
+            // dropped by `publish`, events were lost
+            Err(_) => {
+                let response =
+                    control::Response::error("Too many unread events, subscription dropped");
+
+                if let Ok(mut line) = serde_json::to_string(&response) {
+                    line.push('\n');
+
+                    let _ = stream.write_all(line.as_bytes());
+                }
+
+                return;
+            }
+        };
+
+        if stream.write_all(line.as_bytes()).is_err() {
+            break;
+        }
+    }
+
+    // forget the subscriber now rather than on the next event
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.id != id);
+}
+
+// follow the live event stream, printing every event as a line of text or as
+// raw JSON
+pub fn tail(socket_path: Option<PathBuf>, json: bool, entry: Option<PathBuf>) -> Result<(), Error> {
+    let mut stream = control::connect(socket_path)?;
+
+    let mut line = serde_json::to_string(&control::Request::Subscribe { entry })?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+
+        if json {
+            println!("{}", line);
+            continue;
+        }
+
+        // the daemon answers with an error response rather than a record
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(_) => {
+                let response: control::Response = serde_json::from_str(&line)?;
+
+                return Err(Error::other(response.error.unwrap_or_default()));
+            }
+        };
+
+        let details = match record.event {
+            Event::Fs {
+                operation,
+                path,
+                path_to,
+                exclude
+            } => format!(
+                "{} {}{}{}",
+                operation,
+                path,
+                path_to
+                    .map(|path_to| format!(" -> {}", path_to))
+                    .unwrap_or_default(),
+                exclude
+                    .map(|exclude| format!(" (excluded by {})", exclude))
+                    .unwrap_or_default()
+            ),
+            Event::DebounceStarted { delay } => format!("debounce started, delay {}s", delay),
+            Event::RunStarted { run, paths } => {
+                format!("run {} started, {} paths", run, paths.len())
+            }
+            Event::RunFinished {
+                run,
+                success,
+                wall_time
+            } => format!(
+                "run {} finished, {} in {:.3}s",
+                run,
+                if success { "success" } else { "failure" },
+                wall_time
+            )
+        };
+
+        println!(
+            "{} {}  {}",
+            history::format_timestamp(record.time),
+            record.entry,
+            details
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::logger;
+use std::{
+    env,
+    ffi::OsStr,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram}
+        }
+    },
+    time::Duration
+};
+
+// send `state` (e.g. `READY=1`) to the service manager, if watchd runs as a
+// `Type=notify` service
+pub fn notify(state: &str) {
+    let notify_socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(notify_socket) => notify_socket,
+        None => return
+    };
+
+    if let Err(err) = self::send(&notify_socket, state) {
+        error!(
+            logger::ROOT, "NOTIFY";
+            "error" => true,
+            "state" => state,
+            "message" => err.to_string()
+        );
+    }
+}
+
+// send `RELOADING=1`, with the monotonic timestamp required by
+// `Type=notify-reload`
+pub fn reloading() {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0
+    };
+
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+
+    self::notify(&format!(
+        "RELOADING=1\nMONOTONIC_USEC={}",
+        now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000
+    ));
+}
+
+// interval between `WATCHDOG=1` pings, half of the service manager's timeout,
+// if the watchdog is enabled for this process
+pub fn watchdog() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse().ok())
+        .filter(|&usec| usec > 0)
+        .map(|usec| Duration::from_micros(usec) / 2)
+}
+
+fn send(notify_socket: &OsStr, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    // a leading `@` denotes an abstract socket
+    let address = match notify_socket.as_bytes() {
+        [b'@', name @ ..] => SocketAddr::from_abstract_name(name)?,
+        path => SocketAddr::from_pathname(OsStr::from_bytes(path))?
+    };
+
+    socket.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::{
+    io::{Error, Read},
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant}
+};
+
+pub(super) struct Execution {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub wall_time: Duration,
+    pub user_time: Duration,
+    pub system_time: Duration
+}
+
+// execute `command` with `sh -c ...`, passing `paths` as positional parameters
+// (`$@`) without any conversion
+pub(super) fn execute(command: &str, paths: &[PathBuf]) -> Result<Execution, Error> {
+    let start = Instant::now();
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("watchd")
+        .args(paths)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // drain both pipes concurrently, so that the child never blocks on a full pipe
+    let stdout = self::drain(child.stdout.take());
+    let stderr = self::drain(child.stderr.take());
+
+    // reap the child with `wait4` instead of `Child::wait`, which gives us the
+    // resource usage of this child alone
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    loop {
+        let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut rusage) };
+
+        if pid != -1 {
+            break;
+        }
+
+        let err = Error::last_os_error();
+
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    Ok(Execution {
+        status: ExitStatus::from_raw(status),
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+        wall_time: start.elapsed(),
+        user_time: self::duration(rusage.ru_utime),
+        system_time: self::duration(rusage.ru_stime)
+    })
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = vec![];
+
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+
+        buffer
+    })
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
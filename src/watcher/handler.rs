@@ -1,12 +1,16 @@
-use super::Message;
-use crate::{config, logger};
+use super::{
+    command,
+    output::{self, Output},
+    Message
+};
+use crate::{config, history, logger, metrics, state, status, stream};
 use std::{
-    path::PathBuf,
-    process::Command,
-    str,
+    collections::BTreeSet,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
     sync::mpsc::{Receiver, RecvTimeoutError},
     thread,
-    time::Duration
+    time::{Duration, Instant, SystemTime}
 };
 
 struct Pending {
@@ -26,14 +30,9 @@ pub(super) fn spawn(
     thread::Builder::new()
         .name(thread_name.clone())
         .spawn(move || {
-            let thread_log = logger::ROOT.new(o!(
-                "thread" => thread_name,
-                "id" => format!("{}", entry_path.display())
-            ));
+            let thread_log = logger::entry(&entry_path).new(o!("thread" => thread_name));
 
-            if config::OPTS.read().unwrap().verbose {
-                info!(thread_log, "SPAWN");
-            }
+            debug!(thread_log, "SPAWN");
 
             // if `try_init` and `init` are true, run the command first thing in the loop
             // (`try_init` is true only when this function is called by Watcher::new)
@@ -43,8 +42,13 @@ pub(super) fn spawn(
                 terminate: false
             };
 
-            if config::OPTS.read().unwrap().verbose && pending.command {
-                info!(
+            // paths received since the last command execution
+            let mut paths = BTreeSet::new();
+            // time of the first event received since the last command execution
+            let mut first_event = None;
+
+            if pending.command {
+                debug!(
                     thread_log, "INIT";
                     "sync" => true
                 );
@@ -56,14 +60,26 @@ pub(super) fn spawn(
                     loop {
                         // note that either `recv` or `recv_timeout` can update the value of
                         // `pending.command`
-                        pending = if config::OPTS.read().unwrap().entries[&entry_path].delay == 0.0 {
+                        pending = if self::entry(&entry_path, |entry| entry.delay).unwrap_or_default()
+                            == 0.0
+                        {
                             // handle null `delay`
-                            self::recv(&thread_log, &shared_rx)
+                            self::recv(&thread_log, &shared_rx, &entry_path, &mut paths)
                         }
                         else {
-                            self::recv_timeout(&thread_log, &shared_rx, pending.command, &entry_path)
+                            self::recv_timeout(
+                                &thread_log,
+                                &shared_rx,
+                                pending.command,
+                                &entry_path,
+                                &mut paths
+                            )
                         };
 
+                        if pending.command && first_event.is_none() {
+                            first_event = Some(Instant::now());
+                        }
+
                         if pending.terminate {
                             info!(thread_log, "TERMINATE");
 
@@ -81,69 +97,247 @@ pub(super) fn spawn(
                 }
 
                 if pending.command {
+                    if let Some(first_event) = first_event.take() {
+                        metrics::DEBOUNCE_LATENCY
+                            .with_label_values(&[&entry_path.display().to_string()])
+                            .observe(first_event.elapsed().as_secs_f64());
+                    }
+
                     // log the commands
                     if config::OPTS.read().unwrap().dry_run {
                         info!(
                             thread_log, "RUN";
                             "mode" => "dry",
-                            "commands" => format!("{:?}", config::OPTS.read().unwrap().entries[&entry_path].commands)
+                            "commands" => format!("{:?}", self::entry(&entry_path, |entry| entry.commands.to_owned()).unwrap_or_default())
                         );
                     }
-                    // execute the commands with `sh -c ...`
+                    // execute the commands
                     else {
-                        for command in &config::OPTS.read().unwrap().entries[&entry_path].commands {
-                            info!(
-                                thread_log, "RUN";
-                                "command" => command
-                            );
-
-                            let output = Command::new("sh")
-                                .arg("-c")
-                                .arg(&command)
-                                .output()
-                                .expect("Error while executing command");
-
-                            if config::OPTS.read().unwrap().verbose {
-                                info!(
-                                    thread_log, "OUTPUT";
-                                    "stdout" => match str::from_utf8(&output.stdout) {
-                                        Ok(value) => value,
-                                        Err(err) => panic!("Could not parse stdout: {}", err)
-                                    },
-                                    "stderr" => match str::from_utf8(&output.stderr) {
-                                        Ok(value) => value,
-                                        Err(err) => panic!("Could not parse stderr: {}", err)
-                                    }
-                                );
-                            }
-
-                            if !output.status.success() {
-                                break;
-                            }
-                        }
+                        self::run(&thread_log, &entry_path, &paths);
+
+                        status::write();
                     }
 
                     // notify that a command was executed
                     pending.command = false;
+                    paths.clear();
                 }
             }
         })
         .expect("Could not spawn handler thread")
 }
 
-fn recv(thread_log: &slog::Logger, shared_rx: &Receiver<Message>) -> Pending {
+fn run(thread_log: &slog::Logger, entry_path: &Path, paths: &BTreeSet<PathBuf>) {
+    // copy the options of the entry rather than holding the configuration lock
+    // while the commands run, which would block a reload (and any reader queued
+    // behind it) until they finish
+    let (commands, output_dir, keep_runs) = match self::entry(entry_path, |entry| {
+        (
+            entry.commands.to_owned(),
+            entry.output_dir.to_owned(),
+            entry.keep_runs
+        )
+    }) {
+        Some(options) => options,
+        None => return
+    };
+
+    let start = Instant::now();
+    let (mut user_time, mut system_time) = (Duration::default(), Duration::default());
+
+    let paths: Vec<PathBuf> = paths.iter().cloned().collect();
+    let mut run = history::Run::new(entry_path, &paths, SystemTime::now());
+
+    state::update(entry_path, |entry| entry.running = true);
+    metrics::RUNS_STARTED
+        .with_label_values(&[&entry_path.display().to_string()])
+        .inc();
+    stream::publish(entry_path, stream::Event::RunStarted {
+        run: run.id,
+        paths: run.paths.clone()
+    });
+
+    // retain the output of the run on disk
+    let mut output = match &output_dir {
+        Some(output_dir) => match Output::create(output_dir, &run) {
+            Ok(output) => Some(output),
+            Err(err) => {
+                error!(
+                    thread_log, "OUTPUT";
+                    "error" => true,
+                    "path" => output_dir.display(),
+                    "message" => err.to_string()
+                );
+
+                None
+            }
+        },
+        None => None
+    };
+
+    for command in &commands {
+        info!(
+            thread_log, "RUN";
+            "command" => command
+        );
+
+        let execution = match command::execute(command, &paths) {
+            Ok(execution) => execution,
+            Err(err) => {
+                error!(
+                    thread_log, "EXIT";
+                    "command" => command,
+                    "error" => true,
+                    "message" => err.to_string()
+                );
+
+                run.success = false;
+                run.commands.push(history::CommandRun {
+                    command: command.to_owned(),
+                    exit_code: None,
+                    signal: None,
+                    wall_time: 0.0,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    error: Some(err.to_string())
+                });
+
+                break;
+            }
+        };
+
+        debug!(
+            thread_log, "OUTPUT";
+            "stdout" => %String::from_utf8_lossy(&execution.stdout),
+            "stderr" => %String::from_utf8_lossy(&execution.stderr)
+        );
+
+        if let Some(output) = output.as_mut() {
+            if let Err(err) = output.write(&execution.stdout, &execution.stderr) {
+                error!(
+                    thread_log, "OUTPUT";
+                    "error" => true,
+                    "message" => err.to_string()
+                );
+            }
+        }
+
+        metrics::COMMAND_DURATION
+            .with_label_values(&[&entry_path.display().to_string()])
+            .observe(execution.wall_time.as_secs_f64());
+
+        user_time += execution.user_time;
+        system_time += execution.system_time;
+
+        info!(
+            thread_log, "EXIT";
+            "command" => command,
+            "status" => if execution.status.success() { "success" } else { "failure" },
+            "exit-code" => execution.status.code(),
+            "signal" => execution.status.signal(),
+            "wall-time" => execution.wall_time.as_secs_f64(),
+            "user-time" => execution.user_time.as_secs_f64(),
+            "system-time" => execution.system_time.as_secs_f64()
+        );
+
+        run.success = execution.status.success();
+        run.commands.push(history::CommandRun {
+            command: command.to_owned(),
+            exit_code: execution.status.code(),
+            signal: execution.status.signal(),
+            wall_time: execution.wall_time.as_secs_f64(),
+            stdout: history::CommandRun::truncate(&execution.stdout),
+            stderr: history::CommandRun::truncate(&execution.stderr),
+            error: None
+        });
+
+        // abort the remaining commands
+        if !run.success {
+            break;
+        }
+    }
+
+    run.end = history::timestamp(SystemTime::now());
+
+    metrics::RUNS
+        .with_label_values(&[
+            &entry_path.display().to_string(),
+            if run.success { "success" } else { "failure" }
+        ])
+        .inc();
+
+    state::update(entry_path, |entry| {
+        entry.running = false;
+        entry.last_run = Some(run.clone());
+    });
+
+    if let Err(err) = history::record(&run) {
+        error!(
+            thread_log, "HISTORY";
+            "error" => true,
+            "message" => err.to_string()
+        );
+    }
+
+    // remove the output of older runs
+    if let (Some(output_dir), Some(_)) = (&output_dir, output) {
+        if let Err(err) = output::rotate(output_dir, keep_runs) {
+            error!(
+                thread_log, "OUTPUT";
+                "error" => true,
+                "path" => output_dir.display(),
+                "message" => err.to_string()
+            );
+        }
+    }
+
+    info!(
+        thread_log, "FINISH";
+        "run" => run.id,
+        "status" => if run.success { "success" } else { "failure" },
+        "aborted" => run.commands.len() < commands.len(),
+        "commands" => run.commands.len(),
+        "paths" => paths.len(),
+        "wall-time" => start.elapsed().as_secs_f64(),
+        "user-time" => user_time.as_secs_f64(),
+        "system-time" => system_time.as_secs_f64()
+    );
+    stream::publish(entry_path, stream::Event::RunFinished {
+        run: run.id,
+        success: run.success,
+        wall_time: start.elapsed().as_secs_f64()
+    });
+}
+
+fn recv(
+    thread_log: &slog::Logger,
+    shared_rx: &Receiver<Message>,
+    entry_path: &Path,
+    paths: &mut BTreeSet<PathBuf>
+) -> Pending {
     // received an event
-    match shared_rx.recv() {
+    let message = shared_rx.recv();
+    self::dequeued(entry_path, &message);
+
+    match message {
         // terminate
         Ok(Message::Terminate) => Pending {
             command: false,
             loop_break: false,
             terminate: true
         },
-        // watcher event
-        Ok(Message::Path(_)) => {
+        // watcher event, ignored while paused
+        Ok(Message::Path(_)) if state::paused(entry_path) => Pending {
+            command: false,
+            loop_break: false,
+            terminate: false
+        },
+        Ok(Message::Path(path)) => {
             info!(thread_log, "EVENT");
 
+            state::event(entry_path);
+            paths.insert(path);
+
             // notify that a command execution is pending
             Pending {
                 command: true,
@@ -151,6 +345,16 @@ fn recv(thread_log: &slog::Logger, shared_rx: &Receiver<Message>) -> Pending {
                 terminate: false
             }
         }
+        // manual trigger
+        Ok(Message::Trigger) => {
+            info!(thread_log, "TRIGGER");
+
+            Pending {
+                command: true,
+                loop_break: true,
+                terminate: false
+            }
+        }
         // death, for real
         Err(err) => {
             crit!(
@@ -168,22 +372,40 @@ fn recv_timeout(
     thread_log: &slog::Logger,
     shared_rx: &Receiver<Message>,
     pending_command: bool,
-    entry_path: &PathBuf
+    entry_path: &Path,
+    paths: &mut BTreeSet<PathBuf>
 ) -> Pending {
+    // do not hold the configuration lock while waiting
+    let delay = self::entry(entry_path, |entry| entry.delay).unwrap_or_default();
+
     // received an event before timeout elapsed
-    match shared_rx.recv_timeout(Duration::from_millis(
-        (config::OPTS.read().unwrap().entries[entry_path].delay * 1000_f64) as u64
-    )) {
+    let message = shared_rx.recv_timeout(Duration::from_millis((delay * 1000_f64) as u64));
+    self::dequeued(entry_path, &message);
+
+    match message {
         // terminate
         Ok(Message::Terminate) => Pending {
             command: false,
             loop_break: false,
             terminate: true
         },
-        // watcher event
-        Ok(Message::Path(_)) => {
+        // watcher event, ignored while paused
+        Ok(Message::Path(_)) if state::paused(entry_path) => Pending {
+            command: pending_command,
+            loop_break: false,
+            terminate: false
+        },
+        Ok(Message::Path(path)) => {
             info!(thread_log, "EVENT");
 
+            // first event of a batch
+            if !pending_command {
+                stream::publish(entry_path, stream::Event::DebounceStarted { delay });
+            }
+
+            state::event(entry_path);
+            paths.insert(path);
+
             // notify that a command execution is pending
             Pending {
                 command: true,
@@ -191,6 +413,16 @@ fn recv_timeout(
                 terminate: false
             }
         }
+        // manual trigger, without waiting for `delay`
+        Ok(Message::Trigger) => {
+            info!(thread_log, "TRIGGER");
+
+            Pending {
+                command: true,
+                loop_break: true,
+                terminate: false
+            }
+        }
         // no event received before timeout
         Err(RecvTimeoutError::Timeout) => Pending {
             command: pending_command,
@@ -209,3 +441,18 @@ fn recv_timeout(
         }
     }
 }
+
+// `f` applied to the options of the entry, or None if a reload removed it while
+// its terminate message is still queued
+fn entry<T, F: FnOnce(&config::Entry) -> T>(entry_path: &Path, f: F) -> Option<T> {
+    config::OPTS.read().unwrap().entries.get(entry_path).map(f)
+}
+
+// account for a watcher event leaving `shared_rx`
+fn dequeued<E>(entry_path: &Path, message: &Result<Message, E>) {
+    if let Ok(Message::Path(_)) = message {
+        metrics::QUEUE_DEPTH
+            .with_label_values(&[&entry_path.display().to_string()])
+            .dec();
+    }
+}
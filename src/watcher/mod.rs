@@ -1,41 +1,226 @@
+mod command;
 pub mod handler;
+mod output;
 
-use crate::{config, logger};
+use crate::{config, logger, metrics, state, stream};
 use notify::{DebouncedEvent, Error, RecursiveMode, Watcher as WatcherTrait};
+use regex::bytes::Regex;
 use std::{
-    path::PathBuf,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     sync::mpsc::{channel, Receiver, Sender},
     thread,
-    time::Duration
+    time::{Duration, Instant}
 };
 
 pub enum Message {
-    Path(String),
+    Path(PathBuf),
+    Trigger,
     Terminate
 }
 
+// delay before restarting crashed threads, doubled at every crash
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+// crashes after which an entry is marked as failed rather than restarted
+const MAX_CRASHES: u32 = 5;
+// uptime after which past crashes are forgotten
+const CRASH_RESET: Duration = Duration::from_secs(600);
+
 pub struct Watcher {
     pub entry_path: PathBuf,
-    data: WatcherData
+    data: WatcherData,
+    // supervision of the threads
+    crashes: u32,
+    started: Instant,
+    restart_at: Option<Instant>,
+    failed: bool
 }
 
 struct WatcherData {
-    _watcher_thread: thread::JoinHandle<()>,
-    _handler_thread: thread::JoinHandle<()>,
+    watcher_thread: Option<thread::JoinHandle<()>>,
+    handler_thread: Option<thread::JoinHandle<()>>,
     shared_tx: Sender<Message>,
-    watcher_tx: Sender<DebouncedEvent>
+    watcher_tx: Sender<DebouncedEvent>,
+    registered_rx: Receiver<()>
 }
 
 impl Watcher {
     pub fn new(entry_path: PathBuf) -> Self {
         Self {
             entry_path: entry_path.to_owned(),
-            data: self::WatcherData::new(&entry_path, true)
+            data: self::WatcherData::new(&entry_path, true),
+            crashes: 0,
+            started: Instant::now(),
+            restart_at: None,
+            failed: false
+        }
+    }
+
+    // stop both threads, waiting for the watcher thread only: the handler thread
+    // exits on its own once its current run, if any, is over
+    pub fn stop(mut self) {
+        self.terminate();
+
+        if let Some(thread) = self.data.watcher_thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    // register the watch again (e.g. after `recursive` changed), leaving the
+    // handler and its pending events alone
+    pub fn rewatch(&mut self) {
+        let _ = self
+            .data
+            .watcher_tx
+            .send(DebouncedEvent::Error(Error::WatchNotFound, None));
+
+        if let Some(thread) = self.data.watcher_thread.take() {
+            let _ = thread.join();
         }
+
+        let (watcher_tx, watcher_rx) = channel();
+        let (registered_tx, registered_rx) = channel();
+
+        self.data.watcher_thread = Some(self::spawn(
+            self.entry_path.to_owned(),
+            self.data.shared_tx.clone(),
+            watcher_tx.clone(),
+            watcher_rx,
+            registered_tx
+        ));
+        self.data.watcher_tx = watcher_tx;
+        self.data.registered_rx = registered_rx;
     }
 
-    pub fn restart(&mut self) {
+    // start over with new threads, forgetting past crashes
+    pub fn respawn(&mut self) {
+        self.terminate();
+
         self.data = self::WatcherData::new(&self.entry_path, false);
+        self.crashes = 0;
+        self.started = Instant::now();
+        self.restart_at = None;
+        self.failed = false;
+    }
+
+    // wait for the watcher thread to register its watch, returning whether it
+    // succeeded
+    pub fn registered(&self) -> bool {
+        let _ = self.data.registered_rx.recv();
+
+        state::get(&self.entry_path).error.is_none()
+    }
+
+    // whether both the watcher and handler threads are running
+    pub fn alive(&self) -> bool {
+        [&self.data.watcher_thread, &self.data.handler_thread]
+            .iter()
+            .all(|thread| thread.as_ref().is_some_and(|thread| !thread.is_finished()))
+    }
+
+    // restart the threads if either of them died, with increasing delays, and give
+    // up after `MAX_CRASHES` crashes
+    pub fn supervise(&mut self) {
+        if self.failed {
+            return;
+        }
+
+        if let Some(restart_at) = self.restart_at {
+            if Instant::now() >= restart_at {
+                info!(
+                    logger::entry(&self.entry_path), "RESTART";
+                    "crashes" => self.crashes
+                );
+
+                self.data = self::WatcherData::new(&self.entry_path, false);
+                self.started = Instant::now();
+                self.restart_at = None;
+            }
+
+            return;
+        }
+
+        if self.alive() {
+            return;
+        }
+
+        let message = self.reap();
+
+        if self.started.elapsed() >= CRASH_RESET {
+            self.crashes = 0;
+        }
+
+        self.crashes += 1;
+
+        if self.crashes >= MAX_CRASHES {
+            crit!(
+                logger::entry(&self.entry_path), "CRASH";
+                "error" => true,
+                "message" => &message,
+                "crashes" => self.crashes,
+                "status" => "failed"
+            );
+
+            let error = format!("Crashed {} times: {}", self.crashes, message);
+            state::update(&self.entry_path, |entry| entry.error = Some(error));
+
+            self.failed = true;
+
+            return;
+        }
+
+        let backoff = std::cmp::min(
+            RESTART_BACKOFF * 2_u32.pow(self.crashes - 1),
+            MAX_RESTART_BACKOFF
+        );
+
+        error!(
+            logger::entry(&self.entry_path), "CRASH";
+            "error" => true,
+            "message" => &message,
+            "crashes" => self.crashes,
+            "restart-in" => backoff.as_secs_f64()
+        );
+
+        self.restart_at = Some(Instant::now() + backoff);
+    }
+
+    // stop the surviving thread, if any, and collect the cause of death of the
+    // others
+    fn reap(&mut self) -> String {
+        let mut messages = Vec::new();
+
+        for thread in [&mut self.data.watcher_thread, &mut self.data.handler_thread] {
+            // leave the survivor alone, it is terminated below
+            let thread = match thread.take_if(|thread| thread.is_finished()) {
+                Some(thread) => thread,
+                None => continue
+            };
+
+            let name = thread.thread().name().unwrap_or_default().to_owned();
+
+            messages.push(match thread.join() {
+                Ok(()) => format!("{} exited", name),
+                Err(panic) => format!(
+                    "{} panicked: {}",
+                    name,
+                    panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default()
+                )
+            });
+        }
+
+        self.terminate();
+
+        messages.join(", ")
+    }
+
+    pub fn trigger(&self) {
+        let _ = self.data.shared_tx.send(Message::Trigger);
     }
 
     pub fn terminate(&self) {
@@ -51,17 +236,25 @@ impl WatcherData {
     fn new(entry_path: &PathBuf, try_init: bool) -> Self {
         let (shared_tx, shared_rx) = channel();
         let (watcher_tx, watcher_rx) = channel();
+        let (registered_tx, registered_rx) = channel();
+
+        // events left in the channel of previous threads are gone with it
+        metrics::QUEUE_DEPTH
+            .with_label_values(&[&entry_path.display().to_string()])
+            .set(0);
 
         Self {
-            _watcher_thread: self::spawn(
+            watcher_thread: Some(self::spawn(
                 entry_path.to_owned(),
                 shared_tx.clone(),
                 watcher_tx.clone(),
-                watcher_rx
-            ),
-            _handler_thread: handler::spawn(entry_path.to_owned(), try_init, shared_rx),
+                watcher_rx,
+                registered_tx
+            )),
+            handler_thread: Some(handler::spawn(entry_path.to_owned(), try_init, shared_rx)),
             shared_tx,
-            watcher_tx
+            watcher_tx,
+            registered_rx
         }
     }
 }
@@ -70,7 +263,8 @@ fn spawn(
     entry_path: PathBuf,
     shared_tx: Sender<Message>,
     tx: Sender<DebouncedEvent>,
-    rx: Receiver<DebouncedEvent>
+    rx: Receiver<DebouncedEvent>,
+    registered_tx: Sender<()>
 ) -> thread::JoinHandle<()> {
     // generate thread name for logging purposes
     let thread_name = format!("watcher-{}", entry_path.display());
@@ -78,39 +272,68 @@ fn spawn(
     thread::Builder::new()
         .name(thread_name.to_owned())
         .spawn(move || {
-            // debounced (10ms) events watcher
-            let mut watcher = notify::watcher(tx, Duration::from_millis(10)).unwrap();
-
-            // add entry `path` to the watcher
-            watcher
-                .watch(
-                    &entry_path,
-                    if config::OPTS.read().unwrap().entries[&entry_path].recursive {
-                        RecursiveMode::Recursive
-                    }
-                    else {
-                        RecursiveMode::NonRecursive
-                    }
-                )
-                .unwrap();
-
             // instantiate thread-local logger
-            let thread_log = logger::ROOT.new(o!(
-                "thread" => thread_name,
-                "id" => format!("{}", entry_path.display())
-            ));
+            let thread_log = logger::entry(&entry_path).new(o!("thread" => thread_name));
+
+            // entry label of the metrics
+            let entry_label = entry_path.display().to_string();
+
+            let recursive = config::OPTS
+                .read()
+                .unwrap()
+                .entries
+                .get(&entry_path)
+                .is_some_and(|entry| entry.recursive);
 
-            if config::OPTS.read().unwrap().verbose {
-                info!(thread_log, "SPAWN");
+            let recursive_mode = if recursive {
+                RecursiveMode::Recursive
             }
+            else {
+                RecursiveMode::NonRecursive
+            };
+
+            // debounced (10ms) events watcher, with entry `path` added; kept until the
+            // thread exits
+            let _watcher =
+                match notify::watcher(tx, Duration::from_millis(10)).and_then(|mut watcher| {
+                    watcher.watch(&entry_path, recursive_mode).map(|()| watcher)
+                }) {
+                    Ok(watcher) => watcher,
+                    Err(err) => {
+                        error!(
+                            thread_log, "WATCH";
+                            "error" => true,
+                            "message" => err.to_string()
+                        );
+
+                        state::update(&entry_path, |entry| entry.error = Some(err.to_string()));
+                        let _ = registered_tx.send(());
+
+                        return;
+                    }
+                };
+
+            state::update(&entry_path, |entry| entry.error = None);
+            metrics::WATCHES.inc();
+
+            // notify that the watch is registered, once
+            let _ = registered_tx.send(());
+            drop(registered_tx);
+
+            debug!(thread_log, "SPAWN");
 
             // watch for events on `rx`
             'event_loop: loop {
-                match rx.recv() {
+                let event = rx.recv();
+                let operation = event.as_ref().map(self::operation).unwrap_or_default();
+
+                match event {
                     // terminate
                     Ok(DebouncedEvent::Error(Error::WatchNotFound, None)) => {
                         info!(thread_log, "TERMINATE");
 
+                        metrics::WATCHES.dec();
+
                         // exit from thread
                         break;
                     }
@@ -119,52 +342,73 @@ fn spawn(
                     | Ok(DebouncedEvent::Write(path))
                     | Ok(DebouncedEvent::Chmod(path))
                     | Ok(DebouncedEvent::Remove(path)) => {
-                        let path = path.to_str().expect("Could not parse path");
-
-                        // test path against excludes
-                        for exclude in &config::OPTS.read().unwrap().entries[&entry_path].excludes {
-                            if exclude.is_match(path) {
-                                if config::OPTS.read().unwrap().verbose {
-                                    info!(
-                                        thread_log, "EVENT";
-                                        "exclude" => true,
-                                        "pattern" => exclude.as_str(),
-                                        "path" => path
-                                    );
-                                }
+                        // test path against excludes (raw bytes, paths need not be UTF-8)
+                        for exclude in self::excludes(&entry_path) {
+                            if exclude.is_match(path.as_os_str().as_bytes()) {
+                                debug!(
+                                    thread_log, "EVENT";
+                                    "exclude" => true,
+                                    "pattern" => exclude.as_str(),
+                                    "path" => path.display()
+                                );
+                                stream::publish(&entry_path, stream::Event::Fs {
+                                    operation: operation.to_owned(),
+                                    path: path.display().to_string(),
+                                    path_to: None,
+                                    exclude: Some(exclude.as_str().to_owned())
+                                });
+
+                                metrics::EVENTS_EXCLUDED
+                                    .with_label_values(&[&entry_label, operation])
+                                    .inc();
 
                                 // ignore; continue to next received event
                                 continue 'event_loop;
                             }
                         }
 
-                        if config::OPTS.read().unwrap().verbose {
-                            info!(
-                                thread_log, "EVENT";
-                                "path" => path
-                            );
-                        }
+                        debug!(
+                            thread_log, "EVENT";
+                            "path" => path.display()
+                        );
+                        stream::publish(&entry_path, stream::Event::Fs {
+                            operation: operation.to_owned(),
+                            path: path.display().to_string(),
+                            path_to: None,
+                            exclude: None
+                        });
+
+                        metrics::EVENTS
+                            .with_label_values(&[&entry_label, operation])
+                            .inc();
 
                         // forward event to the shared channel
-                        let _ = shared_tx.send(Message::Path(path.to_owned()));
+                        self::forward(&shared_tx, &entry_label, path);
                     }
                     // multiple file operation
                     Ok(DebouncedEvent::Rename(path_from, path_to)) => {
-                        let path_from = path_from.to_str().expect("Could not parse path_from");
-                        let path_to = path_to.to_str().expect("Could not parse path_to");
-
                         // test both paths against excludes
-                        for exclude in &config::OPTS.read().unwrap().entries[&entry_path].excludes {
-                            if exclude.is_match(path_from) || exclude.is_match(path_to) {
-                                if config::OPTS.read().unwrap().verbose {
-                                    info!(
-                                        thread_log, "EVENT";
-                                        "exclude" => true,
-                                        "pattern" => exclude.as_str(),
-                                        "path-from" => path_from,
-                                        "path-to" => path_to
-                                    );
-                                }
+                        for exclude in self::excludes(&entry_path) {
+                            if exclude.is_match(path_from.as_os_str().as_bytes())
+                                || exclude.is_match(path_to.as_os_str().as_bytes())
+                            {
+                                debug!(
+                                    thread_log, "EVENT";
+                                    "exclude" => true,
+                                    "pattern" => exclude.as_str(),
+                                    "path-from" => path_from.display(),
+                                    "path-to" => path_to.display()
+                                );
+                                stream::publish(&entry_path, stream::Event::Fs {
+                                    operation: operation.to_owned(),
+                                    path: path_from.display().to_string(),
+                                    path_to: Some(path_to.display().to_string()),
+                                    exclude: Some(exclude.as_str().to_owned())
+                                });
+
+                                metrics::EVENTS_EXCLUDED
+                                    .with_label_values(&[&entry_label, operation])
+                                    .inc();
 
                                 // ignore; continue to next received event
                                 continue 'event_loop;
@@ -173,13 +417,23 @@ fn spawn(
 
                         info!(
                             thread_log, "EVENT";
-                            "path-from" => path_from,
-                            "path-to" => path_to
+                            "path-from" => path_from.display(),
+                            "path-to" => path_to.display()
                         );
+                        stream::publish(&entry_path, stream::Event::Fs {
+                            operation: operation.to_owned(),
+                            path: path_from.display().to_string(),
+                            path_to: Some(path_to.display().to_string()),
+                            exclude: None
+                        });
+
+                        metrics::EVENTS
+                            .with_label_values(&[&entry_label, operation])
+                            .inc();
 
                         // forward event to the shared channel
-                        let _ = shared_tx.send(Message::Path(path_from.to_owned()));
-                        let _ = shared_tx.send(Message::Path(path_to.to_owned()));
+                        self::forward(&shared_tx, &entry_label, path_from);
+                        self::forward(&shared_tx, &entry_label, path_to);
                     }
                     // death
                     Err(err) => {
@@ -199,3 +453,37 @@ fn spawn(
         })
         .expect("Could not spawn watcher thread")
 }
+
+// expressions excluding paths from the entry, none if a reload removed it while
+// its terminate message is still queued
+fn excludes(entry_path: &Path) -> Vec<Regex> {
+    config::OPTS
+        .read()
+        .unwrap()
+        .entries
+        .get(entry_path)
+        .map(|entry| entry.excludes.to_owned())
+        .unwrap_or_default()
+}
+
+fn operation(event: &DebouncedEvent) -> &'static str {
+    match event {
+        DebouncedEvent::Create(_) => "create",
+        DebouncedEvent::Write(_) => "write",
+        DebouncedEvent::Chmod(_) => "chmod",
+        DebouncedEvent::Remove(_) => "remove",
+        DebouncedEvent::Rename(..) => "rename",
+        _ => "other"
+    }
+}
+
+// send `path` to the handler, accounting for it in the queue depth
+fn forward(shared_tx: &Sender<Message>, entry_label: &str, path: PathBuf) {
+    let queue_depth = metrics::QUEUE_DEPTH.with_label_values(&[entry_label]);
+
+    queue_depth.inc();
+
+    if shared_tx.send(Message::Path(path)).is_err() {
+        queue_depth.dec();
+    }
+}
@@ -0,0 +1,111 @@
+use crate::history;
+use std::{
+    fs::{self, File},
+    io::{Error, Write},
+    path::{Path, PathBuf}
+};
+
+// stdout/stderr files of a single run
+pub(super) struct Output {
+    stdout: File,
+    stderr: File
+}
+
+impl Output {
+    // create `<output_dir>/<id>-<timestamp>.{stdout,stderr}`
+    pub fn create(output_dir: &Path, run: &history::Run) -> Result<Self, Error> {
+        fs::create_dir_all(output_dir)?;
+
+        let name = format!("{}-{}", run.id, history::format_timestamp(run.start));
+
+        Ok(Self {
+            stdout: File::create(output_dir.join(format!("{}.stdout", name)))?,
+            stderr: File::create(output_dir.join(format!("{}.stderr", name)))?
+        })
+    }
+
+    pub fn write(&mut self, stdout: &[u8], stderr: &[u8]) -> Result<(), Error> {
+        self.stdout.write_all(stdout)?;
+        self.stderr.write_all(stderr)
+    }
+}
+
+// remove the output files of all but the latest `keep_runs` runs in
+// `output_dir`
+pub(super) fn rotate(output_dir: &Path, keep_runs: usize) -> Result<(), Error> {
+    // (timestamp, id, path) of every output file
+    let mut files: Vec<(String, u64, PathBuf)> = fs::read_dir(output_dir)?
+        .filter_map(|dir_entry| {
+            let path = dir_entry.ok()?.path();
+            let name = path.file_stem()?.to_str()?;
+            let (id, timestamp) = name.split_at(name.find('-')?);
+
+            match path.extension()?.to_str()? {
+                "stdout" | "stderr" => Some((timestamp.to_owned(), id.parse().ok()?, path)),
+                _ => None
+            }
+        })
+        .collect();
+
+    // newest runs first
+    files.sort_by(|a, b| (&b.0, b.1).cmp(&(&a.0, a.1)));
+
+    let mut runs = vec![];
+
+    for (timestamp, id, path) in files {
+        if !runs.contains(&(timestamp.to_owned(), id)) {
+            runs.push((timestamp, id));
+        }
+
+        if runs.len() > keep_runs {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_runs() {
+        let dir = std::env::temp_dir().join(format!("watchd-rotate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // ids restart at 1 with a new history file, the start time wins
+        let runs = [
+            (8, "2026-01-01T00:00:00Z"),
+            (9, "2026-01-01T00:00:01Z"),
+            (1, "2026-01-02T00:00:00Z"),
+            (2, "2026-01-02T00:00:01Z")
+        ];
+
+        for (id, timestamp) in &runs {
+            for extension in &["stdout", "stderr"] {
+                fs::write(dir.join(format!("{}-{}.{}", id, timestamp, extension)), "").unwrap();
+            }
+        }
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        rotate(&dir, 2).unwrap();
+
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|dir_entry| dir_entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+
+        assert_eq!(files, vec![
+            "1-2026-01-02T00:00:00Z.stderr",
+            "1-2026-01-02T00:00:00Z.stdout",
+            "2-2026-01-02T00:00:01Z.stderr",
+            "2-2026-01-02T00:00:01Z.stdout",
+            "notes.txt"
+        ]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}